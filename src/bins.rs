@@ -1,13 +1,10 @@
 use std::collections::HashMap;
-use std::fmt::Debug;
-use std::iter::Map;
 use std::ops::Deref;
-use std::slice::Iter;
-use chrono::{Date, DateTime, Utc};
+use chrono::Date;
+use chrono_tz::Europe::Helsinki;
 use chrono_tz::Tz;
-use rust_decimal::Decimal;
 use crate::datebin::DateBin;
-use crate::record::{PricedRecord, Record};
+use crate::record::Record;
 
 pub struct Bins<R>
     where
@@ -38,28 +35,38 @@ impl<'a, R> IntoIterator for &'a Bins<R>
 }
 
 
-impl<T, R> From<T> for Bins<R>
-    where
-        T: Iterator<Item = R>,
-        R: Record,
+impl<R> Bins<R>
+where
+    R: Record,
 {
-    fn from(records: T) -> Self {
-        let mut map: HashMap<Date<Utc>, Vec<R>> = HashMap::default();
+    /// Groups `records` by the calendar day in `timezone` on which their interval starts, so that
+    /// a day has 23 or 25 hours when daylight saving time changes
+    pub fn new(records: impl IntoIterator<Item = R>, timezone: Tz) -> Self {
+        let mut map: HashMap<Date<Tz>, Vec<R>> = HashMap::default();
         for x in records {
-            let date = x.date_time().date();
-            map.entry(date)
-                .and_modify(|v| v.push(x))
-                .or_default();
+            let date = x.start_time().with_timezone(&timezone).date();
+            map.entry(date).or_default().push(x);
         }
 
         let mut s = map
             .into_iter()
             .map(|(date, records)| DateBin::new(date, records))
             .collect::<Vec<_>>();
-        s.sort_by(|a, b| a.date.cmp(&b.date));
+        s.sort_by_key(|a| a.date);
         Bins { bins: s }
     }
 }
+
+/// Bins by days in Finnish time, see [`Bins::new`]
+impl<T, R> From<T> for Bins<R>
+    where
+        T: Iterator<Item = R>,
+        R: Record,
+{
+    fn from(records: T) -> Self {
+        Bins::new(records, Helsinki)
+    }
+}
 impl<R> Deref for Bins<R>
 where
     R: Record
@@ -69,4 +76,85 @@ where
     fn deref(&self) -> &Self::Target {
         &self.bins
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use chrono::{Datelike, DateTime, Duration, TimeZone, Utc};
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::record::oomi::OomiRecord;
+
+    fn reading(hour: u32, energy: i64) -> OomiRecord {
+        OomiRecord {
+            date_time: Utc.ymd(2023, 1, 10).and_hms(hour, 0, 0),
+            energy: Decimal::from(energy),
+            ..Default::default()
+        }
+    }
+
+    /// Hourly readings of 1 kWh from local midnight of `from` to local midnight of `to`
+    fn hours(from: (i32, u32, u32), to: (i32, u32, u32)) -> Vec<OomiRecord> {
+        let midnight = |(y, m, d): (i32, u32, u32)| -> DateTime<Utc> {
+            Helsinki.ymd(y, m, d).and_hms(0, 0, 0).with_timezone(&Utc)
+        };
+        let (start, end) = (midnight(from), midnight(to));
+        (1..=(end - start).num_hours())
+            .map(|hour| OomiRecord {
+                date_time: start + Duration::hours(hour),
+                energy: Decimal::ONE,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn day_lengths(bins: &Bins<OomiRecord>) -> Vec<(u32, Decimal)> {
+        bins.iter().map(|bin| (bin.date.day(), bin.hours())).collect()
+    }
+
+    #[test]
+    fn keeps_every_reading_of_a_day() {
+        let bins = Bins::from(vec![reading(10, 1), reading(11, 2)].into_iter());
+
+        assert_eq!(bins.len(), 1);
+        assert_eq!(bins[0].records().len(), 2);
+        assert_eq!(bins[0].energy_sum(), Decimal::from(3));
+    }
+
+    #[test]
+    fn days_change_at_local_midnight() {
+        // the hour ending at local midnight belongs to the previous day
+        let bins = Bins::new(hours((2023, 1, 9), (2023, 1, 11)), Helsinki);
+
+        let days = day_lengths(&bins);
+        assert_eq!(days, vec![(9, Decimal::from(24)), (10, Decimal::from(24))]);
+        let last = bins[0].records().iter().map(|r| r.date_time).max().unwrap();
+        assert_eq!(last, Helsinki.ymd(2023, 1, 10).and_hms(0, 0, 0).with_timezone(&Utc));
+    }
+
+    #[test]
+    fn day_of_the_spring_transition_has_23_hours() {
+        let bins = Bins::new(hours((2023, 3, 25), (2023, 3, 28)), Helsinki);
+
+        let days = day_lengths(&bins);
+        assert_eq!(
+            days,
+            vec![(25, Decimal::from(24)), (26, Decimal::from(23)), (27, Decimal::from(24))]
+        );
+        assert_eq!(bins[1].energy_sum(), Decimal::from(23));
+        assert_eq!(bins[1].hourly_average(), Decimal::ONE);
+    }
+
+    #[test]
+    fn day_of_the_autumn_transition_has_25_hours() {
+        let bins = Bins::new(hours((2023, 10, 28), (2023, 10, 31)), Helsinki);
+
+        let days = day_lengths(&bins);
+        assert_eq!(
+            days,
+            vec![(28, Decimal::from(24)), (29, Decimal::from(25)), (30, Decimal::from(24))]
+        );
+        assert_eq!(bins[1].energy_sum(), Decimal::from(25));
+        assert_eq!(bins[1].hourly_average(), Decimal::ONE);
+    }
+}
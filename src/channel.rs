@@ -0,0 +1,108 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+
+use crate::error::Error;
+use crate::record::{Direction, Record};
+
+/// Readings of one metering point in one direction. Records from different channels must not be
+/// summed together.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Channel {
+    pub metering_point: Option<u64>,
+    pub direction: Direction,
+}
+
+impl Channel {
+    pub fn of<R>(record: &R) -> Self
+    where
+        R: Record,
+    {
+        Channel {
+            metering_point: record.metering_point(),
+            direction: record.direction(),
+        }
+    }
+}
+
+impl Display for Channel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.metering_point {
+            Some(metering_point) => write!(f, "{} {}", metering_point, self.direction),
+            None => write!(f, "{}", self.direction),
+        }
+    }
+}
+
+/// Groups `records` by metering point and direction, keeping their order within each channel
+pub fn split_channels<R>(records: impl IntoIterator<Item = R>) -> BTreeMap<Channel, Vec<R>>
+where
+    R: Record,
+{
+    let mut channels: BTreeMap<Channel, Vec<R>> = BTreeMap::new();
+    for record in records {
+        channels.entry(Channel::of(&record)).or_default().push(record);
+    }
+    channels
+}
+
+/// Which records of a file to use
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Selection {
+    /// Required when the file has several metering points in `direction`
+    pub metering_point: Option<u64>,
+    pub direction: Direction,
+}
+
+impl Selection {
+    pub fn new(metering_point: Option<u64>, direction: Direction) -> Self {
+        Selection {
+            metering_point,
+            direction,
+        }
+    }
+
+    /// Whether `record` can belong to the selection. Used to drop records while reading, before
+    /// [`Selection::select`] checks that the metering point is unambiguous.
+    pub fn accepts<R>(&self, record: &R) -> bool
+    where
+        R: Record,
+    {
+        record.direction() == self.direction
+            && self
+                .metering_point
+                .is_none_or(|m| record.metering_point() == Some(m))
+    }
+
+    /// Keeps the records of the selected channel
+    pub fn select<R>(&self, records: Vec<R>) -> Result<Vec<R>, Error>
+    where
+        R: Record,
+    {
+        let records = records
+            .into_iter()
+            .filter(|r| r.direction() == self.direction)
+            .collect::<Vec<_>>();
+        let available = records
+            .iter()
+            .filter_map(|r| r.metering_point())
+            .collect::<BTreeSet<_>>();
+
+        match self.metering_point {
+            Some(requested) if !records.is_empty() && !available.contains(&requested) => {
+                Err(Error::MeteringPoint {
+                    requested: Some(requested),
+                    available: available.into_iter().collect(),
+                })
+            }
+            Some(requested) => Ok(records
+                .into_iter()
+                .filter(|r| r.metering_point() == Some(requested))
+                .collect()),
+            None if available.len() > 1 => Err(Error::MeteringPoint {
+                requested: None,
+                available: available.into_iter().collect(),
+            }),
+            None => Ok(records),
+        }
+    }
+}
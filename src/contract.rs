@@ -0,0 +1,376 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Datelike, Timelike, Utc, Weekday};
+use chrono_tz::Tz;
+use rust_decimal::Decimal;
+
+use crate::plotter::contract_price_label;
+use crate::record::{Record, RecordWithPrice};
+
+/// Pricing of an electricity contract. Prices are in c/kWh and costs in cents.
+pub trait Contract<R>
+where
+    R: Record,
+{
+    /// Legend text of the contract
+    fn name(&self) -> String;
+
+    /// Cost of the energy in `record`
+    fn cost(&self, record: &RecordWithPrice<R>) -> Decimal;
+
+    /// Costs of `records` in the same order. Override when the cost of a record depends on the
+    /// other records, e.g. when spreading a fixed fee.
+    fn costs(&self, records: &[RecordWithPrice<R>]) -> Vec<Decimal> {
+        records.iter().map(|r| self.cost(r)).collect()
+    }
+}
+
+impl<R> Contract<R> for Box<dyn Contract<R>>
+where
+    R: Record,
+{
+    fn name(&self) -> String {
+        self.as_ref().name()
+    }
+
+    fn cost(&self, record: &RecordWithPrice<R>) -> Decimal {
+        self.as_ref().cost(record)
+    }
+
+    fn costs(&self, records: &[RecordWithPrice<R>]) -> Vec<Decimal> {
+        self.as_ref().costs(records)
+    }
+}
+
+/// Same price for every kWh
+#[derive(Debug, Copy, Clone)]
+pub struct FixedPrice {
+    pub price: Decimal,
+}
+
+impl FixedPrice {
+    pub fn new(price: Decimal) -> Self {
+        FixedPrice { price }
+    }
+}
+
+impl<R> Contract<R> for FixedPrice
+where
+    R: Record,
+{
+    fn name(&self) -> String {
+        contract_price_label(self.price)
+    }
+
+    fn cost(&self, record: &RecordWithPrice<R>) -> Decimal {
+        self.price * record.record.energy()
+    }
+}
+
+/// Spot price with a margin added to every kWh
+#[derive(Debug, Copy, Clone)]
+pub struct SpotMargin {
+    pub margin: Decimal,
+}
+
+impl SpotMargin {
+    pub fn new(margin: Decimal) -> Self {
+        SpotMargin { margin }
+    }
+}
+
+impl<R> Contract<R> for SpotMargin
+where
+    R: Record,
+{
+    fn name(&self) -> String {
+        format!("Pörssi + {}", contract_price_label(self.margin))
+    }
+
+    fn cost(&self, record: &RecordWithPrice<R>) -> Decimal {
+        (record.price + self.margin) * record.record.energy()
+    }
+}
+
+/// The usual Finnish spot contract, "spot + margin c/kWh + €/month"
+pub type SpotContract = MonthlyFee<SpotMargin>;
+
+impl SpotContract {
+    pub fn spot(margin: Decimal, monthly_fee: Decimal, timezone: Tz) -> Self {
+        MonthlyFee::new(SpotMargin::new(margin), monthly_fee, timezone)
+    }
+}
+
+/// Day/night time-of-use price. Night time is 22-07 local time.
+#[derive(Debug, Copy, Clone)]
+pub struct TimeOfUse {
+    pub day_price: Decimal,
+    pub night_price: Decimal,
+    pub timezone: Tz,
+}
+
+impl TimeOfUse {
+    pub fn new(day_price: Decimal, night_price: Decimal, timezone: Tz) -> Self {
+        TimeOfUse {
+            day_price,
+            night_price,
+            timezone,
+        }
+    }
+
+    pub fn is_day(&self, time: &DateTime<Utc>) -> bool {
+        let hour = time.with_timezone(&self.timezone).hour();
+        (7..22).contains(&hour)
+    }
+}
+
+impl<R> Contract<R> for TimeOfUse
+where
+    R: Record,
+{
+    fn name(&self) -> String {
+        format!(
+            "Päivä {} / yö {}",
+            contract_price_label(self.day_price),
+            contract_price_label(self.night_price)
+        )
+    }
+
+    fn cost(&self, record: &RecordWithPrice<R>) -> Decimal {
+        let price = if self.is_day(&record.record.start_time()) {
+            self.day_price
+        } else {
+            self.night_price
+        };
+        price * record.record.energy()
+    }
+}
+
+/// Seasonal price. Winter weekday is November-March, Monday-Saturday 07-22 local time.
+#[derive(Debug, Copy, Clone)]
+pub struct Seasonal {
+    pub winter_weekday_price: Decimal,
+    pub other_price: Decimal,
+    pub timezone: Tz,
+}
+
+impl Seasonal {
+    pub fn new(winter_weekday_price: Decimal, other_price: Decimal, timezone: Tz) -> Self {
+        Seasonal {
+            winter_weekday_price,
+            other_price,
+            timezone,
+        }
+    }
+
+    pub fn is_winter_weekday(&self, time: &DateTime<Utc>) -> bool {
+        let local = time.with_timezone(&self.timezone);
+        let winter = local.month() >= 11 || local.month() <= 3;
+        let weekday = local.weekday() != Weekday::Sun;
+        winter && weekday && (7..22).contains(&local.hour())
+    }
+}
+
+impl<R> Contract<R> for Seasonal
+where
+    R: Record,
+{
+    fn name(&self) -> String {
+        format!(
+            "Talviarkipäivä {} / muu aika {}",
+            contract_price_label(self.winter_weekday_price),
+            contract_price_label(self.other_price)
+        )
+    }
+
+    fn cost(&self, record: &RecordWithPrice<R>) -> Decimal {
+        let price = if self.is_winter_weekday(&record.record.start_time()) {
+            self.winter_weekday_price
+        } else {
+            self.other_price
+        };
+        price * record.record.energy()
+    }
+}
+
+/// Adds a monthly base fee in euros on top of another contract. The fee of each calendar month
+/// in local time is divided evenly between the records of that month. A month that is only
+/// partly compared is charged the whole fee, like the contract would bill it.
+#[derive(Debug, Copy, Clone)]
+pub struct MonthlyFee<C> {
+    pub contract: C,
+    pub fee: Decimal,
+    pub timezone: Tz,
+}
+
+impl<C> MonthlyFee<C> {
+    pub fn new(contract: C, fee: Decimal, timezone: Tz) -> Self {
+        MonthlyFee {
+            contract,
+            fee,
+            timezone,
+        }
+    }
+}
+
+/// Divides the fee in euros of each calendar month in local time, given by `fee(year, month)`,
+/// evenly between the records of that month, in cents. The whole fee is divided also when the
+/// records cover only part of the month.
+pub(crate) fn spread_monthly_fee<R>(
+    records: &[RecordWithPrice<R>],
+    timezone: Tz,
+    fee: impl Fn(i32, u32) -> Decimal,
+) -> Vec<Decimal>
+where
+    R: Record,
+{
+    let month = |time: &DateTime<Utc>| {
+        let local = time.with_timezone(&timezone);
+        (local.year(), local.month())
+    };
+
+    let mut records_per_month: HashMap<(i32, u32), usize> = HashMap::new();
+    for record in records {
+        *records_per_month
+            .entry(month(&record.record.start_time()))
+            .or_default() += 1;
+    }
+
+    records
+        .iter()
+        .map(|record| {
+            let (year, month) = month(&record.record.start_time());
+            let count = records_per_month[&(year, month)];
+            fee(year, month) * Decimal::from(100) / Decimal::from(count)
+        })
+        .collect()
+}
+
+impl<C, R> Contract<R> for MonthlyFee<C>
+where
+    C: Contract<R>,
+    R: Record,
+{
+    fn name(&self) -> String {
+        format!("{} + {} €/kk", self.contract.name(), self.fee.normalize()).replace('.', ",")
+    }
+
+    /// Cost without the fee, which can only be divided when all records of the month are known
+    fn cost(&self, record: &RecordWithPrice<R>) -> Decimal {
+        self.contract.cost(record)
+    }
+
+    fn costs(&self, records: &[RecordWithPrice<R>]) -> Vec<Decimal> {
+        self.contract
+            .costs(records)
+            .into_iter()
+            .zip(spread_monthly_fee(records, self.timezone, |_, _| self.fee))
+            .map(|(cost, fee)| cost + fee)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone};
+    use chrono_tz::Europe::Helsinki;
+
+    use super::*;
+    use crate::record::oomi::OomiRecord;
+
+    /// Hourly reading starting at `start` local time in Helsinki
+    fn reading(start: (i32, u32, u32, u32), energy: i64) -> OomiRecord {
+        let (year, month, day, hour) = start;
+        OomiRecord {
+            date_time: Helsinki.ymd(year, month, day).and_hms(hour, 0, 0).with_timezone(&Utc)
+                + Duration::hours(1),
+            energy: Decimal::from(energy),
+            ..Default::default()
+        }
+    }
+
+    fn costs<C>(contract: &C, records: &[OomiRecord], spot: i64) -> Vec<Decimal>
+    where
+        C: Contract<OomiRecord>,
+    {
+        let records = records
+            .iter()
+            .map(|r| RecordWithPrice::new(r, Decimal::from(spot)))
+            .collect::<Vec<_>>();
+        contract.costs(&records)
+    }
+
+    #[test]
+    fn fixed_price_costs() {
+        let records = [reading((2023, 1, 10, 12), 2)];
+        assert_eq!(costs(&FixedPrice::new(Decimal::from(10)), &records, 5), vec![Decimal::from(20)]);
+    }
+
+    #[test]
+    fn spot_margin_costs() {
+        let records = [reading((2023, 1, 10, 12), 2)];
+        assert_eq!(costs(&SpotMargin::new(Decimal::ONE), &records, 5), vec![Decimal::from(12)]);
+    }
+
+    #[test]
+    fn time_of_use_costs() {
+        let contract = TimeOfUse::new(Decimal::from(10), Decimal::from(5), Helsinki);
+        let records = [
+            reading((2023, 1, 10, 6), 1),
+            reading((2023, 1, 10, 7), 1),
+            reading((2023, 1, 10, 21), 1),
+            reading((2023, 1, 10, 22), 1),
+        ];
+        assert_eq!(
+            costs(&contract, &records, 0),
+            vec![Decimal::from(5), Decimal::from(10), Decimal::from(10), Decimal::from(5)]
+        );
+    }
+
+    #[test]
+    fn seasonal_costs() {
+        let contract = Seasonal::new(Decimal::from(10), Decimal::from(5), Helsinki);
+        let records = [
+            // Tuesday in January
+            reading((2023, 1, 10, 7), 1),
+            reading((2023, 1, 10, 22), 1),
+            // Sunday
+            reading((2023, 1, 15, 12), 1),
+            // Saturday
+            reading((2023, 1, 14, 12), 1),
+            reading((2023, 3, 31, 12), 1),
+            reading((2023, 4, 3, 12), 1),
+            reading((2023, 11, 1, 12), 1),
+        ];
+        let prices = [10, 5, 5, 10, 10, 5, 10].map(Decimal::from).to_vec();
+        assert_eq!(costs(&contract, &records, 0), prices);
+    }
+
+    #[test]
+    fn monthly_fee_is_spread_over_the_local_month() {
+        let contract = SpotContract::spot(Decimal::ONE, Decimal::from(3), Helsinki);
+        let records = [
+            reading((2023, 1, 30, 12), 1),
+            reading((2023, 1, 31, 23), 1),
+            // 22:00 UTC on the 31st
+            reading((2023, 2, 1, 0), 1),
+        ];
+        // spot 5 + margin 1 and 3 € split between the two readings of January
+        assert_eq!(
+            costs(&contract, &records, 5),
+            vec![Decimal::from(156), Decimal::from(156), Decimal::from(306)]
+        );
+    }
+
+    #[test]
+    fn partial_month_is_charged_the_whole_fee() {
+        let records = [reading((2023, 2, 1, 0), 1), reading((2023, 2, 1, 1), 1)];
+        let records = records
+            .iter()
+            .map(|r| RecordWithPrice::new(r, Decimal::ZERO))
+            .collect::<Vec<_>>();
+        let fees = spread_monthly_fee(&records, Helsinki, |_, _| Decimal::from(3));
+
+        assert_eq!(fees, vec![Decimal::from(150), Decimal::from(150)]);
+    }
+}
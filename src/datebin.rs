@@ -1,15 +1,18 @@
 use std::fmt::{Display, Formatter};
-use std::ops::{Deref, Index};
+use std::ops::Deref;
 
-use chrono::{Date, TimeZone, Utc};
+use chrono::{Date, TimeZone};
+use chrono_tz::Europe::Helsinki;
+use chrono_tz::Tz;
 use rust_decimal::prelude::*;
 
-use crate::record::{oomi, PricedRecord, Record, RecordWithPrice};
+use crate::record::Record;
 
 #[derive(Debug, Clone)]
 pub struct DateBin<T>
 where T: Record {
-    pub date: Date<Utc>,
+    /// Local calendar day
+    pub date: Date<Tz>,
     /// Sorted by energy
     records: Vec<T>,
     median: usize,
@@ -25,7 +28,7 @@ where T: Record
 
 impl<R> DateBin<R>
 where R: Record {
-    pub fn new(date: Date<Utc>, records: Vec<R>) -> Self {
+    pub fn new(date: Date<Tz>, records: Vec<R>) -> Self {
         let mut energy: Vec<_> = records;
         energy.sort_by_key(|r| r.energy());
         let median = energy.len() / 2;
@@ -44,8 +47,24 @@ where R: Record {
         self.records.iter().map(|e| e.energy()).sum()
     }
 
+    /// Hours covered by the readings, e.g. 23 or 25 on a complete day when daylight saving time
+    /// changes
+    pub fn hours(&self) -> Decimal {
+        let minutes = self
+            .records
+            .iter()
+            .map(|r| r.resolution().duration().num_minutes())
+            .sum::<i64>();
+        Decimal::from(minutes) / Decimal::from(60)
+    }
+
+    /// Average energy per hour of the hours that have readings
     pub fn hourly_average(&self) -> Decimal {
-        self.energy_sum() / Decimal::from(24)
+        let hours = self.hours();
+        if hours.is_zero() {
+            return Decimal::ZERO;
+        }
+        self.energy_sum() / hours
     }
 
     pub fn above_percentile(&self, percent: Decimal) -> impl Iterator<Item = &R> {
@@ -69,7 +88,7 @@ where R: Record {
         self.records().get(self.median)
     }
 
-    pub fn nth_percentile(&self, percentile: Decimal) -> NthPercentile<R> {
+    pub fn nth_percentile(&self, percentile: Decimal) -> NthPercentile<'_, R> {
         NthPercentile {
             bin: self,
             nth_percentile: percentile,
@@ -101,13 +120,13 @@ where T: Record {
             .map(|r| r.energy())
             .sum::<Decimal>();
         let date = self.date.naive_local();
-        let median = self.median_energy_record().unwrap().energy();
+        let median = self.median_energy_record().map(|r| r.energy()).unwrap_or_default();
         write!(f, "date: {}, total: {:.5} kWh, average: {:.5} kWh, median: {:.5} kWh, above {:.0}th percentile: {:.5} kWh)"
                , date
                , total
                , avg
                , median
-               , self.nth_percentile * Decimal::from_f32(100.0).unwrap()
+               , self.nth_percentile * Decimal::from(100)
                , above)
     }
 }
@@ -117,9 +136,55 @@ impl<T> Default for DateBin<T>
 where T: Record {
     fn default() -> Self {
         DateBin {
-            date: Utc.ymd(2000, 1, 1),
+            date: Helsinki.ymd(2000, 1, 1),
             records: Vec::default(),
             median: 0,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::record::oomi::OomiRecord;
+    use crate::record::TimeResolution;
+
+    fn readings(resolution: TimeResolution, energies: &[Decimal]) -> Vec<OomiRecord> {
+        let start = Utc.ymd(2023, 1, 10).and_hms(0, 0, 0);
+        energies
+            .iter()
+            .enumerate()
+            .map(|(i, energy)| OomiRecord {
+                date_time: start + resolution.duration() * (i as i32 + 1),
+                energy: *energy,
+                resolution,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn hourly_average_is_per_hour_of_readings() {
+        let date = Helsinki.ymd(2023, 1, 10);
+        let hourly = DateBin::new(date, readings(TimeResolution::PT1H, &[dec!(1), dec!(2), dec!(6)]));
+        assert_eq!(hourly.hours(), Decimal::from(3));
+        assert_eq!(hourly.hourly_average(), Decimal::from(3));
+
+        // six quarters are one and a half hours
+        let quarters = DateBin::new(date, readings(TimeResolution::PT15M, &[dec!(0.5); 6]));
+        assert_eq!(quarters.hours(), dec!(1.5));
+        assert_eq!(quarters.hourly_average(), Decimal::from(2));
+    }
+
+    #[test]
+    fn empty_bin_has_no_average_or_median() {
+        let bin = DateBin::<OomiRecord>::default();
+
+        assert_eq!(bin.hourly_average(), Decimal::ZERO);
+        assert!(bin.median_energy_record().is_none());
+        assert!(bin.nth_percentile(dec!(0.9)).to_string().contains("median: 0.00000 kWh"));
+    }
+}
@@ -0,0 +1,187 @@
+use std::fmt::{Display, Formatter};
+use std::io::{BufRead, BufReader, Cursor, Read};
+use std::path::Path;
+
+use chrono::{DateTime, NaiveDateTime};
+
+use crate::error::Error;
+use crate::input::Input;
+
+/// Longest header or row read when detecting a format
+const MAX_SNIFFED_LINE: u64 = 64 * 1024;
+
+/// Consumption export formats the crate can parse
+#[derive(clap::ValueEnum, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FileFormat {
+    /// Fingrid Datahub export
+    Fingrid,
+    /// Oomi hourly consumption export
+    Oomi,
+}
+
+impl FileFormat {
+    pub const ALL: [FileFormat; 2] = [FileFormat::Fingrid, FileFormat::Oomi];
+
+    pub fn delimiter(&self) -> char {
+        match self {
+            FileFormat::Fingrid | FileFormat::Oomi => ';',
+        }
+    }
+
+    /// Columns the parser of the format requires
+    pub fn columns(&self) -> &'static [&'static str] {
+        match self {
+            FileFormat::Fingrid => &["Resoluutio", "Alkuaika", "Määrä"],
+            FileFormat::Oomi => &["Tunti", "Energia yhteensä (kWh)"],
+        }
+    }
+
+    fn date_column(&self) -> &'static str {
+        match self {
+            FileFormat::Fingrid => "Alkuaika",
+            FileFormat::Oomi => "Tunti",
+        }
+    }
+
+    fn is_date(&self, value: &str) -> bool {
+        match self {
+            FileFormat::Fingrid => DateTime::parse_from_rfc3339(value).is_ok(),
+            FileFormat::Oomi => NaiveDateTime::parse_from_str(value, "%d.%m.%Y %H:%M").is_ok(),
+        }
+    }
+
+    /// Whether the sniffed start of a file looks like this format. A file without data rows
+    /// matches on the header alone.
+    fn matches(&self, sniffed: &Sniffed) -> bool {
+        let column = |name: &str| sniffed.headers.iter().position(|h| h == name);
+        sniffed.delimiter == self.delimiter()
+            && self.columns().iter().all(|c| column(c).is_some())
+            && match (column(self.date_column()), &sniffed.first_row) {
+                (Some(i), Some(row)) => row.get(i).map(|v| self.is_date(v)).unwrap_or(false),
+                _ => true,
+            }
+    }
+
+    /// Detects the format of `file_path` from its delimiter, header and first date. Compressed
+    /// files are detected from their first entry.
+    pub fn detect(file_path: &Path) -> Result<FileFormat, Error> {
+        match Input::open(file_path)?.into_iter().next() {
+            Some(input) => FileFormat::detect_input(input).map(|(format, _)| format),
+            None => FileFormat::from_sniffed(file_path, &Sniffed::default()),
+        }
+    }
+
+    /// Detects the format of `input` like [`FileFormat::detect`]. The returned input reads the
+    /// whole of `input` again, so that also a stream like stdin can be detected and then parsed.
+    pub fn detect_input(input: Input<'_>) -> Result<(FileFormat, Input<'_>), Error> {
+        let Input { name, reader } = input;
+        let mut reader = BufReader::new(reader);
+        let mut head = Vec::new();
+        for _ in 0..2 {
+            (&mut reader)
+                .take(MAX_SNIFFED_LINE)
+                .read_until(b'\n', &mut head)
+                .map_err(|e| Error::io(&name, e))?;
+        }
+
+        let format = FileFormat::from_sniffed(&name, &Sniffed::from_head(&head))?;
+        Ok((format, Input::new(name, Cursor::new(head).chain(reader))))
+    }
+
+    fn from_sniffed(name: &Path, sniffed: &Sniffed) -> Result<FileFormat, Error> {
+        FileFormat::ALL
+            .into_iter()
+            .find(|format| format.matches(sniffed))
+            .ok_or_else(|| Error::UnsupportedFormat {
+                path: name.to_path_buf(),
+                expected: FileFormat::ALL
+                    .iter()
+                    .map(|f| format!("{} ({})", f, f.columns().join(&f.delimiter().to_string())))
+                    .collect::<Vec<_>>()
+                    .join(" or "),
+            })
+    }
+}
+
+impl Display for FileFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileFormat::Fingrid => write!(f, "Fingrid Datahub export"),
+            FileFormat::Oomi => write!(f, "Oomi export"),
+        }
+    }
+}
+
+/// Header and first data row of a delimited file
+#[derive(Default)]
+struct Sniffed {
+    delimiter: char,
+    headers: Vec<String>,
+    first_row: Option<Vec<String>>,
+}
+
+impl Sniffed {
+    /// A binary file is not an error here, it just does not match any format
+    fn from_head(head: &[u8]) -> Self {
+        let head = String::from_utf8_lossy(head);
+        let mut lines = head.lines();
+
+        let header = lines.next().unwrap_or_default();
+        let header = header.trim_start_matches('\u{feff}');
+        let delimiter = [';', ',', '\t']
+            .into_iter()
+            .max_by_key(|d| header.matches(*d).count())
+            .unwrap_or(';');
+        let split = |line: &str| {
+            line.split(delimiter)
+                .map(|c| c.trim().trim_matches('"').to_string())
+                .collect::<Vec<_>>()
+        };
+
+        Sniffed {
+            delimiter,
+            headers: split(header),
+            first_row: lines.next().map(split),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect(data: &'static str) -> Result<FileFormat, Error> {
+        let (format, mut input) = FileFormat::detect_input(Input::new("export.csv", data.as_bytes()))?;
+        // the sniffed lines are read again
+        let mut read = String::new();
+        input.reader.read_to_string(&mut read).unwrap();
+        assert_eq!(read, data);
+        Ok(format)
+    }
+
+    #[test]
+    fn detects_an_oomi_export() {
+        let data = "Tunti;Energia yhteensä (kWh);Status;Lämpötila (°C)\n\
+            01.01.2022 00:00;0,5;Mitattu;-5,2\n";
+        assert_eq!(detect(data).unwrap(), FileFormat::Oomi);
+    }
+
+    #[test]
+    fn detects_a_datahub_export() {
+        let data = "\u{feff}Mittauspisteen tunnus;Tuotteen tyyppi;Resoluutio;Yksikkötyyppi;Lukeman tyyppi;Alkuaika;Määrä;Laatu\n\
+            1;8716867000030;PT1H;kWh;BN01;2022-01-01T00:00:00Z;0,5;OK\n";
+        assert_eq!(detect(data).unwrap(), FileFormat::Fingrid);
+    }
+
+    #[test]
+    fn detects_a_datahub_export_with_only_the_required_columns() {
+        let data = "Resoluutio;Alkuaika;Määrä\nPT1H;2022-01-01T00:00:00Z;0,5\n";
+        assert_eq!(detect(data).unwrap(), FileFormat::Fingrid);
+    }
+
+    #[test]
+    fn rejects_an_unknown_header() {
+        let data = "time;kWh\n2022-01-01T00:00:00Z;0,5\n";
+        assert!(matches!(detect(data), Err(Error::UnsupportedFormat { .. })));
+    }
+}
@@ -0,0 +1,170 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing a file failed
+    Io { path: PathBuf, source: io::Error },
+    /// A CSV file or one of its rows could not be read
+    Csv {
+        path: PathBuf,
+        line: Option<u64>,
+        source: csv::Error,
+    },
+    /// A zip archive could not be read
+    Zip {
+        path: PathBuf,
+        source: zip::result::ZipError,
+    },
+    /// A JSON file could not be read
+    Json {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    /// A value is not a valid decimal number
+    Decimal { value: String },
+    /// No spot price covers the metering interval starting at `time`
+    MissingPrice { time: DateTime<Utc> },
+    /// Fetching prices from the remote service failed
+    RemotePrice(reqwest::Error),
+    /// The file is not in a supported format
+    UnsupportedFormat { path: PathBuf, expected: String },
+    /// Overlapping files have different readings for the same time
+    Duplicate {
+        time: DateTime<Utc>,
+        metering_point: Option<u64>,
+    },
+    /// An hour ending at `time` does not have all of its quarter hour readings
+    IncompleteInterval {
+        time: DateTime<Utc>,
+        metering_point: Option<u64>,
+    },
+    /// The requested metering point is not in the file, or the file has several and none was
+    /// requested
+    MeteringPoint {
+        requested: Option<u64>,
+        available: Vec<u64>,
+    },
+}
+
+impl Error {
+    pub(crate) fn io(path: &Path, source: io::Error) -> Self {
+        Error::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    pub(crate) fn csv(path: &Path, source: csv::Error) -> Self {
+        Error::Csv {
+            path: path.to_path_buf(),
+            line: source.position().map(|p| p.line()),
+            source,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::Csv {
+                path,
+                line: Some(line),
+                source,
+            } => write!(f, "{} line {}: {}", path.display(), line, source),
+            Error::Csv {
+                path,
+                line: None,
+                source,
+            } => write!(f, "{}: {}", path.display(), source),
+            Error::Zip { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::Json { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::Decimal { value } => write!(f, "Invalid decimal number '{}'", value),
+            Error::MissingPrice { time } => write!(f, "No price data found for {}", time),
+            Error::RemotePrice(e) => write!(f, "Fetching prices failed: {}", e),
+            Error::UnsupportedFormat { path, expected } => write!(
+                f,
+                "Unsupported file '{}', expected {}",
+                path.display(),
+                expected
+            ),
+            Error::Duplicate {
+                time,
+                metering_point: Some(metering_point),
+            } => write!(
+                f,
+                "Conflicting readings of metering point {} for {}",
+                metering_point, time
+            ),
+            Error::Duplicate {
+                time,
+                metering_point: None,
+            } => write!(f, "Conflicting readings for {}", time),
+            Error::IncompleteInterval {
+                time,
+                metering_point: Some(metering_point),
+            } => write!(
+                f,
+                "Missing quarter hour readings of metering point {} for the hour ending {}",
+                metering_point, time
+            ),
+            Error::IncompleteInterval {
+                time,
+                metering_point: None,
+            } => write!(f, "Missing quarter hour readings for the hour ending {}", time),
+            Error::MeteringPoint {
+                requested: Some(requested),
+                available,
+            } => write!(
+                f,
+                "No readings for metering point {}, the file has {}",
+                requested,
+                join(available)
+            ),
+            Error::MeteringPoint {
+                requested: None,
+                available,
+            } => write!(
+                f,
+                "The file has several metering points, select one of {}",
+                join(available)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Csv { source, .. } => Some(source),
+            Error::Zip { source, .. } => Some(source),
+            Error::Json { source, .. } => Some(source),
+            Error::RemotePrice(e) => Some(e),
+            Error::Decimal { .. }
+            | Error::MissingPrice { .. }
+            | Error::UnsupportedFormat { .. }
+            | Error::Duplicate { .. }
+            | Error::IncompleteInterval { .. }
+            | Error::MeteringPoint { .. } => None,
+        }
+    }
+}
+
+fn join(values: &[u64]) -> String {
+    values
+        .iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::RemotePrice(e)
+    }
+}
@@ -0,0 +1,234 @@
+use std::fs::File;
+use std::io::{self, BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crc32fast::Hasher;
+use flate2::read::{DeflateDecoder, MultiGzDecoder};
+use zip::result::ZipError;
+use zip::{CompressionMethod, ZipArchive};
+
+use crate::error::Error;
+
+/// A named source of CSV data. The name is only used in error messages and reports.
+pub struct Input<'a> {
+    pub name: PathBuf,
+    pub reader: Box<dyn Read + 'a>,
+}
+
+impl<'a> Input<'a> {
+    pub fn new(name: impl Into<PathBuf>, reader: impl Read + 'a) -> Self {
+        Input {
+            name: name.into(),
+            reader: Box::new(reader),
+        }
+    }
+}
+
+impl Input<'static> {
+    /// Opens `file_path`. A `.gz` file is decompressed while reading and a `.zip` file is read as
+    /// its `.csv` entries one after another.
+    pub fn open(file_path: &Path) -> Result<Vec<Input<'static>>, Error> {
+        let extension = file_path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            Some("zip") => zip_entries(file_path),
+            Some("gz") => {
+                let file = File::open(file_path).map_err(|e| Error::io(file_path, e))?;
+                let decoder = MultiGzDecoder::new(BufReader::new(file));
+                Ok(vec![Input::new(file_path, decoder)])
+            }
+            _ => {
+                let file = File::open(file_path).map_err(|e| Error::io(file_path, e))?;
+                Ok(vec![Input::new(file_path, file)])
+            }
+        }
+    }
+
+    /// Standard input, decompressed while reading when it is gzipped
+    pub fn stdin() -> Result<Input<'static>, Error> {
+        let name = PathBuf::from("-");
+        let mut stdin = io::stdin();
+        let mut magic = Vec::with_capacity(GZIP_MAGIC.len());
+        (&mut stdin)
+            .take(GZIP_MAGIC.len() as u64)
+            .read_to_end(&mut magic)
+            .map_err(|e| Error::io(&name, e))?;
+
+        let gzipped = magic == GZIP_MAGIC;
+        let reader = Cursor::new(magic).chain(stdin);
+        if gzipped {
+            Ok(Input::new(name, MultiGzDecoder::new(BufReader::new(reader))))
+        } else {
+            Ok(Input::new(name, reader))
+        }
+    }
+}
+
+/// First bytes of a gzip stream
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+fn zip_entries(file_path: &Path) -> Result<Vec<Input<'static>>, Error> {
+    let zip_error = |source| Error::Zip {
+        path: file_path.to_path_buf(),
+        source,
+    };
+    let file = File::open(file_path).map_err(|e| Error::io(file_path, e))?;
+    let mut archive = ZipArchive::new(BufReader::new(file)).map_err(zip_error)?;
+
+    let mut entries = Vec::new();
+    for index in 0..archive.len() {
+        let entry = archive.by_index_raw(index).map_err(zip_error)?;
+        let name = entry.name().map_err(zip_error)?.to_string();
+        if !entry.is_file() || !name.to_lowercase().ends_with(".csv") {
+            continue;
+        }
+        if entry.encrypted() {
+            return Err(zip_error(ZipError::UnsupportedArchive(ZipError::PASSWORD_REQUIRED)));
+        }
+        let deflated = match entry.compression() {
+            CompressionMethod::Stored => false,
+            CompressionMethod::Deflated => true,
+            _ => return Err(zip_error(ZipError::UnsupportedArchive("only stored and deflated entries are supported"))),
+        };
+        let data_start = entry
+            .data_start()
+            .ok_or_else(|| zip_error(ZipError::InvalidArchive("entry data not found".into())))?;
+        entries.push(Input::new(
+            file_path.join(&name),
+            ZipEntry {
+                archive: file_path.to_path_buf(),
+                data_start,
+                compressed_size: entry.compressed_size(),
+                size: entry.size(),
+                crc32: entry.crc32(),
+                deflated,
+                reader: None,
+                hasher: Hasher::new(),
+                read: 0,
+            },
+        ));
+    }
+
+    if entries.is_empty() {
+        return Err(Error::UnsupportedFormat {
+            path: file_path.to_path_buf(),
+            expected: "a zip file with .csv entries".to_string(),
+        });
+    }
+    Ok(entries)
+}
+
+/// Entry of a zip archive, decompressed while it is read. The archive is opened when the entry is
+/// first read, so only one entry of an archive is open at a time. The size and checksum of the
+/// entry are checked when its end is reached.
+struct ZipEntry {
+    archive: PathBuf,
+    data_start: u64,
+    compressed_size: u64,
+    size: u64,
+    crc32: u32,
+    deflated: bool,
+    reader: Option<Box<dyn Read>>,
+    hasher: Hasher,
+    read: u64,
+}
+
+impl ZipEntry {
+    fn open(&self) -> io::Result<Box<dyn Read>> {
+        let mut file = File::open(&self.archive)?;
+        file.seek(SeekFrom::Start(self.data_start))?;
+        let data = BufReader::new(file).take(self.compressed_size);
+        if self.deflated {
+            Ok(Box::new(DeflateDecoder::new(data)))
+        } else {
+            Ok(Box::new(data))
+        }
+    }
+
+    fn verify(&self) -> io::Result<()> {
+        if self.read != self.size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("zip entry has {} bytes, expected {}", self.read, self.size),
+            ));
+        }
+        if self.hasher.clone().finalize() != self.crc32 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "zip entry checksum does not match"));
+        }
+        Ok(())
+    }
+}
+
+impl Read for ZipEntry {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.reader.is_none() {
+            self.reader = Some(self.open()?);
+        }
+        let read = self.reader.as_mut().map_or(Ok(0), |reader| reader.read(buf))?;
+        if read == 0 && !buf.is_empty() {
+            self.verify()?;
+        }
+        self.hasher.update(&buf[..read]);
+        self.read += read as u64;
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    use super::*;
+
+    #[test]
+    fn streams_stored_and_deflated_entries() {
+        let path = std::env::temp_dir().join(format!("eleparser-input-{}.zip", std::process::id()));
+        let mut writer = ZipWriter::new(File::create(&path).unwrap());
+        for (name, method) in [("a.csv", CompressionMethod::Stored), ("b.csv", CompressionMethod::Deflated)] {
+            writer
+                .start_file(name, SimpleFileOptions::default().compression_method(method))
+                .unwrap();
+            writer.write_all(format!("{};1\n", name).repeat(1000).as_bytes()).unwrap();
+        }
+        writer.start_file("readme.txt", SimpleFileOptions::default()).unwrap();
+        writer.finish().unwrap();
+
+        let inputs = Input::open(&path).unwrap();
+        let contents = inputs
+            .into_iter()
+            .map(|mut input| {
+                let mut data = String::new();
+                input.reader.read_to_string(&mut data).unwrap();
+                data
+            })
+            .collect::<Vec<_>>();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(contents, vec!["a.csv;1\n".repeat(1000), "b.csv;1\n".repeat(1000)]);
+    }
+
+    #[test]
+    fn rejects_a_corrupted_entry() {
+        let path = std::env::temp_dir().join(format!("eleparser-corrupted-{}.zip", std::process::id()));
+        let mut writer = ZipWriter::new(File::create(&path).unwrap());
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        writer.start_file("a.csv", options).unwrap();
+        writer.write_all(b"a;1\nb;2\n").unwrap();
+        writer.finish().unwrap();
+
+        let mut bytes = std::fs::read(&path).unwrap();
+        let data = bytes.windows(8).position(|w| w == b"a;1\nb;2\n").unwrap();
+        bytes[data + 2] = b'9';
+        std::fs::write(&path, bytes).unwrap();
+
+        let mut input = Input::open(&path).unwrap().remove(0);
+        let result = input.reader.read_to_end(&mut Vec::new());
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
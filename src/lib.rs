@@ -1,129 +1,305 @@
 use crate::bins::Bins;
-use chrono::{DateTime, Duration, Timelike, Utc};
-use chrono_tz::Europe::Helsinki;
+use chrono::{DateTime, Duration, Utc};
 use chrono_tz::Tz;
-use rust_decimal::prelude::FromPrimitive;
 use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
 use serde::Serialize;
-use std::collections::HashMap;
-use std::error::Error;
-use std::fmt::Debug;
-use std::ops::Sub;
-use std::path::Path;
-use itertools::Itertools;
-
-use crate::parser::{EnergyParser, FingridParser, Parser};
-use crate::record::fingrid::FingridRecord;
-use crate::record::RecordWithPrice;
-use crate::record::{Record, TimeResolution};
 
-mod bins;
-mod datebin;
-mod parser;
-mod priceclient;
-mod record;
+use crate::channel::Selection;
+use crate::contract::{Contract, SpotContract};
+use crate::input::Input;
+use crate::merge::{merge, DuplicatePolicy, InputFiles};
+use crate::parser::{EnergyParser, ParseReport, Parser};
+use crate::priceclient::{PriceLookup, PriceSource};
+use crate::record::RecordWithPrice;
+use crate::record::Record;
+use crate::tariff::GridCosts;
 
+pub mod bins;
+pub mod channel;
+pub mod contract;
+pub mod datebin;
+pub mod detect;
+mod error;
+pub mod input;
+pub mod merge;
+pub mod netmetering;
+pub mod parser;
 pub mod plotter;
+pub mod pricecache;
+pub mod priceclient;
+pub mod pricefile;
+pub mod quality;
+pub mod record;
+pub mod resample;
+pub mod tariff;
+pub mod vat;
+
+pub use error::Error;
 
-#[derive(Debug, Copy, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct CumulativeComparisonData {
     pub date_time: DateTime<Utc>,
     pub market_price_for_hour: Decimal,
     pub energy: Decimal,
     pub cumulative_market_price: Decimal,
-    pub cumulative_set_price: Decimal,
+    /// Distribution cost of this record including its share of the monthly basic fee
+    pub transfer_cost: Decimal,
+    /// Electricity tax of this record
+    pub electricity_tax: Decimal,
+    /// Cumulative cost of the spot contract including its margin, monthly fee and grid costs
+    pub cumulative_spot_contract_price: Decimal,
+    /// Cumulative cost of each compared contract including grid costs, in the order the
+    /// contracts were given
+    pub cumulative_contract_prices: Vec<Decimal>,
+}
+
+/// Contracts compared against each other and the grid costs added to each of them
+pub struct Pricing<'a, R> {
+    pub spot_contract: &'a SpotContract,
+    /// Compared against the spot contract, in the order they are reported
+    pub contracts: &'a [Box<dyn Contract<R>>],
+    pub grid_costs: &'a GridCosts,
 }
-pub fn cumulative_price_by_day(
-    records: Vec<RecordWithPrice<FingridRecord>>,
-    contract_price: Decimal,
-) -> Vec<CumulativeComparisonData> {
+
+pub fn cumulative_price_by_day<R>(
+    records: Vec<RecordWithPrice<R>>,
+    pricing: &Pricing<R>,
+) -> Vec<CumulativeComparisonData>
+where
+    R: Record,
+{
+    let grid_costs = pricing.grid_costs.costs(&records);
+    let spot_contract_costs = pricing.spot_contract.costs(&records);
+    let contract_costs = pricing
+        .contracts
+        .iter()
+        .map(|c| c.costs(&records))
+        .collect::<Vec<_>>();
+
     let mut current_market_sum = Decimal::from(0);
-    let mut current_constant_sum = Decimal::from(0);
+    let mut current_spot_contract_sum = Decimal::from(0);
+    let mut current_contract_sums = vec![Decimal::from(0); pricing.contracts.len()];
     records
         .iter()
-        .map(|r| {
+        .enumerate()
+        .map(|(i, r)| {
             let market_price_kwh = r.price;
-            let cumulative_market = current_market_sum + (market_price_kwh * r.record.energy);
-            let cumulative_constant = current_constant_sum + (contract_price * r.record.energy);
+            let energy = r.record.energy();
+            let cumulative_market = current_market_sum + (market_price_kwh * energy);
+            let grid_cost = grid_costs[i];
+            let cumulative_spot_contract =
+                current_spot_contract_sum + spot_contract_costs[i] + grid_cost.total();
+            for (sum, costs) in current_contract_sums.iter_mut().zip(&contract_costs) {
+                *sum += costs[i] + grid_cost.total();
+            }
             let data = CumulativeComparisonData {
-                date_time: r.record.date_time,
+                date_time: r.record.date_time(),
                 cumulative_market_price: cumulative_market,
+                transfer_cost: grid_cost.transfer,
+                electricity_tax: grid_cost.tax,
+                cumulative_spot_contract_price: cumulative_spot_contract,
                 market_price_for_hour: market_price_kwh,
-                cumulative_set_price: cumulative_constant,
-                energy: r.record.energy,
+                cumulative_contract_prices: current_contract_sums.clone(),
+                energy,
             };
             current_market_sum = cumulative_market;
-            current_constant_sum = cumulative_constant;
+            current_spot_contract_sum = cumulative_spot_contract;
             data
         })
         .map(|r| CumulativeComparisonData {
             cumulative_market_price: r.cumulative_market_price / Decimal::from(100),
-            cumulative_set_price: r.cumulative_set_price / Decimal::from(100),
+            transfer_cost: r.transfer_cost / Decimal::from(100),
+            electricity_tax: r.electricity_tax / Decimal::from(100),
+            cumulative_spot_contract_price: r.cumulative_spot_contract_price / Decimal::from(100),
+            cumulative_contract_prices: r
+                .cumulative_contract_prices
+                .iter()
+                .map(|p| p / Decimal::from(100))
+                .collect(),
             ..r
         })
         .collect()
 }
 
-fn print<T>(s: impl Iterator<Item = T>)
-where
-    T: Debug,
-{
-    for f in s {
-        println!("{:?}", f);
-    }
-}
-
-fn with_prices<'a>(
+pub(crate) fn with_prices<'a, R, P>(
+    price_source: &P,
     min: &DateTime<Utc>,
     max: &DateTime<Utc>,
-    bins: &'a Bins<FingridRecord>,
-) -> Result<Vec<RecordWithPrice<'a, FingridRecord>>, Box<dyn Error>> {
+    bins: &'a Bins<R>,
+) -> Result<Vec<RecordWithPrice<'a, R>>, Error>
+where
+    R: Record,
+    P: PriceSource + ?Sized,
+{
     let adjusted_start = *min - Duration::hours(1);
-    let prices = priceclient::get_prices(&adjusted_start, max)?;
-    let mut hourly_prices: HashMap<DateTime<Utc>, Decimal> = HashMap::new();
-    for hourly_price in prices {
-        hourly_prices.insert(hourly_price.time, hourly_price.price);
-    }
+    let prices = price_source.get_prices(&adjusted_start, max)?;
+    let prices = PriceLookup::new(&prices);
 
     let records = bins.bins.iter().flat_map(|b| b.records());
     let mut records_with_prices = records
         .map(|r| {
-            let time = r.date_time().with_timezone(&Utc);
-
-            let adjusted_time = match r.resolution {
-                TimeResolution::PT15M => {
-                    let price_time = match time.minute() {
-                        0 => time - Duration::hours(1),
-                        _ => time.with_minute(0).unwrap(),
-                    };
-                    price_time
-                }
-                TimeResolution::PT1H => time - Duration::hours(1),
-            };
-            let price = hourly_prices.get(&adjusted_time).unwrap_or_else(|| {
-                panic!("No price data found for {adjusted_time:?} (original {time:?})")
-            });
-            RecordWithPrice::new(r, *price)
+            let adjusted_time = r.start_time();
+            let price = prices
+                .price(&adjusted_time, r.resolution())
+                .ok_or(Error::MissingPrice { time: adjusted_time })?;
+            Ok(RecordWithPrice::new(r, price))
         })
-        .collect::<Vec<_>>();
-    records_with_prices.sort_by(|a, b| a.record.date_time().cmp(&b.record.date_time()));
-    // bins.bins.iter().map(|record|)
+        .collect::<Result<Vec<_>, Error>>()?;
+    records_with_prices.sort_by_key(|a| a.record.date_time());
     Ok(records_with_prices)
 }
 
-pub fn get_data(
-    file_path: &Path,
+/// Parses the `selection` of `files` as records of type `R` and compares their spot price from
+/// `price_source` with `pricing` for the given time range. The records are binned by days in
+/// `timezone`.
+pub fn get_data<R, P>(
+    price_source: &P,
+    files: &InputFiles,
+    selection: &Selection,
+    start_time: &DateTime<Utc>,
+    end_time: &DateTime<Utc>,
+    timezone: Tz,
+    pricing: &Pricing<R>,
+) -> Result<Vec<CumulativeComparisonData>, Error>
+where
+    R: Record + DeserializeOwned,
+    Parser<R>: EnergyParser<R>,
+    P: PriceSource + ?Sized,
+{
+    let bins = load_bins::<R>(files, selection, start_time, end_time, timezone)?;
+    compare_bins(price_source, &bins, start_time, end_time, pricing)
+}
+
+/// Parses the `selection` of `files` as records of type `R` within the given time range, binned
+/// by days in `timezone`
+pub fn load_bins<R>(
+    files: &InputFiles,
+    selection: &Selection,
+    start_time: &DateTime<Utc>,
+    end_time: &DateTime<Utc>,
+    timezone: Tz,
+) -> Result<Bins<R>, Error>
+where
+    R: Record + DeserializeOwned,
+    Parser<R>: EnergyParser<R>,
+{
+    load(files, selection, start_time, end_time, timezone, false).map(|(bins, _)| bins)
+}
+
+/// Like [`load_bins`], but skips rows that can not be parsed and lists them in the report
+pub fn load_bins_lenient<R>(
+    files: &InputFiles,
+    selection: &Selection,
     start_time: &DateTime<Utc>,
     end_time: &DateTime<Utc>,
-) -> Result<Vec<CumulativeComparisonData>, Box<dyn Error>> {
-    let fingriddata = Parser::<FingridRecord>::parse(file_path);
+    timezone: Tz,
+) -> Result<(Bins<R>, ParseReport), Error>
+where
+    R: Record + DeserializeOwned,
+    Parser<R>: EnergyParser<R>,
+{
+    load(files, selection, start_time, end_time, timezone, true)
+}
 
-    let fingriddata = fingriddata
+fn load<R>(
+    files: &InputFiles,
+    selection: &Selection,
+    start_time: &DateTime<Utc>,
+    end_time: &DateTime<Utc>,
+    timezone: Tz,
+    lenient: bool,
+) -> Result<(Bins<R>, ParseReport), Error>
+where
+    R: Record + DeserializeOwned,
+    Parser<R>: EnergyParser<R>,
+{
+    let sources = files
+        .paths
+        .iter()
+        .map(|path| Input::open(path))
+        .collect::<Result<Vec<_>, _>>()?;
+    let loaded = read_merged(sources, files.duplicates, start_time, end_time, lenient, |r| {
+        selection.accepts(r)
+    })?;
+    Ok((Bins::new(selection.select(loaded.records)?, timezone), loaded.report))
+}
+
+/// Records read from several files and merged
+pub struct Loaded<R> {
+    /// Ordered by time, metering point and direction
+    pub records: Vec<R>,
+    /// Rows skipped in lenient mode
+    pub report: ParseReport,
+    /// Readings dropped because a file had another reading of the same time
+    pub duplicates: usize,
+}
+
+/// Parses `sources`, the inputs of each file oldest file first, as records of type `R` within the
+/// given time range and merges them with `duplicates`. Records `keep` does not accept are dropped
+/// while reading.
+pub fn read_merged<'a, R>(
+    sources: Vec<Vec<Input<'a>>>,
+    duplicates: DuplicatePolicy,
+    start_time: &DateTime<Utc>,
+    end_time: &DateTime<Utc>,
+    lenient: bool,
+    keep: impl Fn(&R) -> bool,
+) -> Result<Loaded<R>, Error>
+where
+    R: Record + DeserializeOwned,
+    Parser<R>: EnergyParser<R>,
+{
+    let mut readers = sources
         .into_iter()
-        .filter(|d| d.date_time <= *end_time && d.date_time >= *start_time);
+        .map(|inputs| Parser::<R>::read(inputs, lenient).within(start_time, end_time))
+        .collect::<Vec<_>>();
+    let sources = readers
+        .iter_mut()
+        .map(|records| records.filter(|r| r.as_ref().map_or(true, &keep)));
+    let merged = merge(sources, duplicates)?;
+
+    let mut report = ParseReport::default();
+    for records in readers {
+        report.merge(records.into_report());
+    }
+    Ok(Loaded {
+        records: merged.records,
+        report,
+        duplicates: merged.duplicates,
+    })
+}
+
+/// Bins the records within the given time range by days in `timezone`
+pub fn bins_within<R>(
+    records: Vec<R>,
+    start_time: &DateTime<Utc>,
+    end_time: &DateTime<Utc>,
+    timezone: Tz,
+) -> Bins<R>
+where
+    R: Record,
+{
+    let records = records
+        .into_iter()
+        .filter(|d| d.date_time() <= *end_time && d.date_time() >= *start_time);
+
+    Bins::new(records, timezone)
+}
 
-    let bins: Bins<FingridRecord> = Bins::from(fingriddata);
-    let record_prices = with_prices(start_time, end_time, &bins)?;
-    let cumulative_series = cumulative_price_by_day(record_prices, Decimal::from(7));
+/// Compares already parsed records like [`get_data`]
+pub fn compare_bins<R, P>(
+    price_source: &P,
+    bins: &Bins<R>,
+    start_time: &DateTime<Utc>,
+    end_time: &DateTime<Utc>,
+    pricing: &Pricing<R>,
+) -> Result<Vec<CumulativeComparisonData>, Error>
+where
+    R: Record,
+    P: PriceSource + ?Sized,
+{
+    let record_prices = with_prices(price_source, start_time, end_time, bins)?;
+    let cumulative_series = cumulative_price_by_day(record_prices, pricing);
     Ok(cumulative_series)
 }
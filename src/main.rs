@@ -1,27 +1,57 @@
 use chrono::prelude::*;
 use chrono_tz::Europe::Helsinki;
+use chrono_tz::Tz;
 use csv::Writer;
-use plotters::chart::ChartBuilder;
-use plotters::prelude::*;
-use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
-use serde::Serialize;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
 use std::error::Error;
 use std::fmt::Debug;
 use std::path::PathBuf;
+use std::process::ExitCode;
+use std::str::FromStr;
 
 use clap::Parser;
-use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
 
-use eleparserlib::{CumulativeComparisonData, plotter};
+use eleparserlib::{read_merged, CumulativeComparisonData, Pricing, plotter};
+use eleparserlib::contract::{Contract, FixedPrice, MonthlyFee, Seasonal, SpotContract, SpotMargin, TimeOfUse};
+use eleparserlib::parser::{EnergyParser, ParseReport};
+use eleparserlib::channel::Selection;
+use eleparserlib::detect::FileFormat;
+use eleparserlib::input::Input;
+use eleparserlib::merge::{DuplicatePolicy, InputFiles};
+use eleparserlib::netmetering::{net_bins, net_metering, FeedIn, NetMeteringData, NetMeteringSummary};
+use eleparserlib::record::{Direction, Record, TimeResolution};
+use eleparserlib::record::fingrid::FingridRecord;
+use eleparserlib::record::oomi::OomiRecord;
+use eleparserlib::priceclient::{PriceSource, SahkotinClient};
+use eleparserlib::pricecache::PriceCache;
+use eleparserlib::pricefile::PriceFile;
+use eleparserlib::quality::{
+    estimated_by_day, estimated_by_month, estimated_periods, without_estimated, EstimatedReadings,
+    EstimatedShare, QualityCheck, QualityReport,
+};
+use eleparserlib::resample::{resample, IncompleteHours};
+use eleparserlib::tariff::{
+    ElectricityTax, GridCosts, MonthlyPowerFee, NetworkTariff, PowerTariff, TaxClass, TransferPricing,
+};
+use eleparserlib::vat::{VatPolicy, WithVat};
 
 #[derive(Parser, Debug, Clone)]
 #[command(author, version, about, long_about = None)]
 pub struct Cli {
-    #[arg(long)]
-    pub file_path: String,
+    /// Consumption export to read, `-` reads stdin. `.gz` and `.zip` files are decompressed. Can be
+    /// given several times and be a directory or a glob pattern, the files are merged oldest first.
+    #[arg(long = "file-path", required = true, num_args = 1..)]
+    pub file_paths: Vec<String>,
 
+    /// How to resolve readings of the same time found in several files
+    #[arg(value_enum, long, default_value_t)]
+    pub duplicates: DuplicatePolicy,
+
+    /// Format of the consumption file, detected from its contents by default
     #[arg(value_enum, long)]
-    pub file_type: ConsumptionFileType,
+    pub file_type: Option<FileFormat>,
 
     #[arg(long)]
     pub start_date: NaiveDate,
@@ -29,98 +59,709 @@ pub struct Cli {
     #[arg(long)]
     pub end_date: Option<NaiveDate>,
 
+    /// Time zone of the dates and of the days consumption is grouped by, Europe/Helsinki by default
     #[arg(long)]
     pub timezone: Option<chrono_tz::Tz>,
 
     #[arg(long)]
     pub sample: Option<usize>,
 
+    /// Read spot prices from a local .csv or .json file instead of sahkotin.fi
+    #[arg(long)]
+    pub price_file: Option<String>,
+
+    /// Cache prices fetched from sahkotin.fi in this directory
+    #[arg(long)]
+    pub cache_dir: Option<String>,
+
+    /// Fixed contract price to compare against, in c/kWh
+    #[arg(long, default_value = "7")]
+    pub contract_price: Decimal,
+
+    /// Legend text for the fixed price line, generated from --contract-price by default
+    #[arg(long)]
+    pub contract_label: Option<String>,
+
+    /// Additional contract to compare against: `fixed=<c/kWh>`, `spot=<margin c/kWh>`,
+    /// `tou=<day c/kWh>/<night c/kWh>` or `seasonal=<winter weekday c/kWh>/<other c/kWh>`.
+    /// A monthly fee in euros can be added with `+`, e.g. `spot=0.49+3.95`
+    #[arg(long = "contract")]
+    pub contracts: Vec<ContractSpec>,
+
+    /// Margin of the spot contract in c/kWh
+    #[arg(long, default_value_t = Decimal::ZERO)]
+    pub spot_margin: Decimal,
+
+    /// Monthly fee of the spot contract in euros
+    #[arg(long, default_value_t = Decimal::ZERO)]
+    pub spot_monthly_fee: Decimal,
+
+    /// Whether spot prices include VAT. Contract prices should be given the same way.
+    #[arg(value_enum, long, default_value_t)]
+    pub vat: VatPolicy,
+
+    /// Add the electricity tax of this class to every contract
+    #[arg(value_enum, long)]
+    pub electricity_tax: Option<TaxClass>,
+
+    /// Distribution tariff added to every contract: `fixed=<c/kWh>`, `tou=<day c/kWh>/<night c/kWh>`
+    /// or `seasonal=<winter weekday c/kWh>/<other c/kWh>`, with an optional monthly basic fee in
+    /// euros, e.g. `tou=4.2/2.6+12.5`
+    #[arg(long)]
+    pub transfer: Option<ContractSpec>,
+
+    /// Power based distribution fee in €/kW per month, charged on the monthly peak demand
+    #[arg(long)]
+    pub power_tariff: Option<Decimal>,
+
+    /// Number of highest hours of each month averaged into the peak demand
+    #[arg(long, default_value = "1")]
+    pub power_peak_hours: usize,
+
+    /// Skip rows that can not be parsed instead of failing, and list them
+    #[arg(long)]
+    pub lenient: bool,
+
+    /// Metering point to use when the file has readings of several metering points
+    #[arg(long)]
+    pub metering_point: Option<u64>,
+
+    /// Analyse grid feed-in netted against consumption, sold at the spot price minus this margin
+    /// in c/kWh
+    #[arg(long)]
+    pub feed_in_margin: Option<Decimal>,
+
+    /// Production metering point when the file has several
+    #[arg(long)]
+    pub feed_in_metering_point: Option<u64>,
+
+    /// Check the consumption series for gaps, duplicates, resolution changes, runs of zero and
+    /// outliers, and print a summary
+    #[arg(long)]
+    pub check_quality: bool,
+
+    /// What to do with consumption the network operator has estimated instead of measured
+    #[arg(value_enum, long, default_value_t)]
+    pub estimated: EstimatedReadings,
+
+    /// Convert the readings to this resolution, e.g. to compare hourly and quarter hour metering
+    #[arg(value_enum, long, ignore_case = true)]
+    pub resolution: Option<TimeResolution>,
+
+    /// What to do with hours that do not have all quarter hour readings when converting to PT1H
+    #[arg(value_enum, long, default_value_t)]
+    pub incomplete_hours: IncompleteHours,
 }
 
-#[derive(clap::ValueEnum, Clone, Debug)]
-pub enum ConsumptionFileType {
-    Oomi,
-    Fingrid,
+#[derive(Clone, Debug)]
+pub enum ContractKind {
+    Fixed(Decimal),
+    Spot(Decimal),
+    TimeOfUse(Decimal, Decimal),
+    Seasonal(Decimal, Decimal),
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let args = Cli::parse();
+#[derive(Clone, Debug)]
+pub struct ContractSpec {
+    pub kind: ContractKind,
+    pub monthly_fee: Option<Decimal>,
+}
 
-    let file_path = PathBuf::from(&args.file_path);
+impl ContractSpec {
+    fn to_contract<R>(&self, timezone: Tz) -> Box<dyn Contract<R>>
+    where
+        R: Record + 'static,
+    {
+        let contract: Box<dyn Contract<R>> = match self.kind {
+            ContractKind::Fixed(price) => Box::new(FixedPrice::new(price)),
+            ContractKind::Spot(margin) => Box::new(SpotMargin::new(margin)),
+            ContractKind::TimeOfUse(day, night) => Box::new(TimeOfUse::new(day, night, timezone)),
+            ContractKind::Seasonal(winter, other) => Box::new(Seasonal::new(winter, other, timezone)),
+        };
+        match self.monthly_fee {
+            Some(fee) => Box::new(MonthlyFee::new(contract, fee, timezone)),
+            None => contract,
+        }
+    }
+}
+
+impl ContractSpec {
+    fn to_network_tariff(&self, timezone: Tz) -> Result<NetworkTariff, String> {
+        let pricing = match self.kind {
+            ContractKind::Fixed(price) => TransferPricing::Flat(price),
+            ContractKind::TimeOfUse(day, night) => TransferPricing::DayNight { day, night },
+            ContractKind::Seasonal(winter_weekday, other) => TransferPricing::Seasonal {
+                winter_weekday,
+                other,
+            },
+            ContractKind::Spot(_) => return Err("Distribution tariff can not be spot priced".to_string()),
+        };
+        Ok(NetworkTariff::new(
+            pricing,
+            self.monthly_fee.unwrap_or_default(),
+            timezone,
+        ))
+    }
+}
+
+impl FromStr for ContractSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let decimal = |d: &str| {
+            Decimal::from_str(d.trim()).map_err(|e| format!("Invalid price '{d}' in '{s}': {e}"))
+        };
+        let pair = |p: &str| match p.split_once('/') {
+            Some((a, b)) => Ok((decimal(a)?, decimal(b)?)),
+            None => Err(format!("Expected two prices separated by '/' in '{s}'")),
+        };
+
+        let (kind, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Expected <type>=<price> in '{s}'"))?;
+        let (value, monthly_fee) = match value.split_once('+') {
+            Some((value, fee)) => (value, Some(decimal(fee)?)),
+            None => (value, None),
+        };
+        let kind = match kind.trim() {
+            "fixed" => ContractKind::Fixed(decimal(value)?),
+            "spot" => ContractKind::Spot(decimal(value)?),
+            "tou" => {
+                let (day, night) = pair(value)?;
+                ContractKind::TimeOfUse(day, night)
+            }
+            "seasonal" => {
+                let (winter, other) = pair(value)?;
+                ContractKind::Seasonal(winter, other)
+            }
+            other => return Err(format!("Unknown contract type '{other}', expected fixed, spot, tou or seasonal")),
+        };
+        Ok(ContractSpec { kind, monthly_fee })
+    }
+}
+
+fn main() -> ExitCode {
+    let args = Cli::parse();
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
 
+fn run(args: Cli) -> Result<(), Box<dyn Error>> {
     let timezone = args.timezone.unwrap_or(Helsinki);
     let start = timezone
         .from_local_datetime(&args.start_date.and_time(NaiveTime::from_hms(0, 0, 0)))
-        .unwrap();
-    let end = args
-        .end_date
-        .map(|d| {
-            timezone
-                .from_local_date(&d)
-                .and_time(NaiveTime::from_hms(23, 59, 59))
-                .unwrap()
-        })
-        .unwrap_or(Local::now().with_timezone(&timezone));
+        .earliest()
+        .ok_or_else(|| format!("{} does not start at midnight in {:?}", args.start_date, timezone))?;
+    let end = match args.end_date {
+        Some(d) => timezone
+            .from_local_datetime(&d.and_time(NaiveTime::from_hms(23, 59, 59)))
+            .latest()
+            .ok_or_else(|| format!("{} does not end at midnight in {:?}", d, timezone))?,
+        None => Local::now().with_timezone(&timezone),
+    };
 
-    let cumulative_series = eleparserlib::get_data(&file_path, &start.with_timezone(&Utc), &end.with_timezone(&Utc))?;
+    let price_source: Box<dyn PriceSource> = match &args.price_file {
+        Some(price_file) => Box::new(PriceFile::from_path(&PathBuf::from(price_file))?),
+        None => match &args.cache_dir {
+            Some(cache_dir) => Box::new(sahkotin_client().with_cache(PriceCache::new(cache_dir))),
+            None => Box::new(sahkotin_client()),
+        },
+    };
+    let price_source = WithVat::new(price_source, args.vat);
 
-    let max_price = cumulative_series
-        .iter()
-        .map(|c| c.market_price_for_hour)
-        .max()
-        .and_then(|d| d.to_f64())
-        .unwrap();
+    let start_utc = start.with_timezone(&Utc);
+    let end_utc = end.with_timezone(&Utc);
+    let sources = open_sources(&args)?;
+    let (file_type, sources) = match args.file_type {
+        Some(file_type) => (file_type, sources),
+        None => detect_format(sources)?,
+    };
+    let comparison = match file_type {
+        FileFormat::Oomi => compare::<OomiRecord>(
+            &args,
+            &price_source,
+            sources,
+            &start_utc,
+            &end_utc,
+            timezone,
+        )?,
+        FileFormat::Fingrid => compare::<FingridRecord>(
+            &args,
+            &price_source,
+            sources,
+            &start_utc,
+            &end_utc,
+            timezone,
+        )?,
+    };
+
+    let Comparison {
+        spot_contract_name,
+        contract_names,
+        data: cumulative_series,
+        net_metering,
+        estimated,
+    } = comparison;
+    if cumulative_series.is_empty() {
+        return Err(format!("No consumption records in {}", args.file_paths.join(", ")).into());
+    }
+
+    if let Some(net_metering) = &net_metering {
+        print_net_metering(&NetMeteringSummary::new(net_metering));
+        write_net_metering_csv(net_metering)?;
+    }
 
-    let max_set = cumulative_series
+    let max_contract = cumulative_series
         .iter()
-        .map(|c| c.cumulative_set_price)
+        .flat_map(|c| c.cumulative_contract_prices.iter().chain([&c.cumulative_spot_contract_price]))
         .max()
-        .and_then(|d| d.to_f64())
-        .unwrap();
+        .copied()
+        .map(to_f64)
+        .transpose()?
+        .unwrap_or_default();
 
     let max_market = cumulative_series
         .iter()
         .map(|c| c.cumulative_market_price)
         .max()
-        .and_then(|d| d.to_f64())
-        .unwrap();
+        .map(to_f64)
+        .transpose()?
+        .unwrap_or_default();
 
-    let max_val = max_set.max(max_market);
-
-    let set_series = cumulative_series.iter().map(|s| {
-        (
-            // s.date_time.signed_duration_since(start).num_days().to_f32().unwrap(),
-            s.date_time,
-            s.cumulative_set_price.to_f64().unwrap(),
-        )
-    });
-    let market_series = cumulative_series.iter().map(|s| {
-        (
-            // s.date_time.signed_duration_since(start).num_days().to_f32().unwrap(),
-            s.date_time,
-            s.cumulative_market_price.to_f64().unwrap(),
-        )
-    });
-    let hourly_price_series = cumulative_series.iter().map(|s| {
-        (
-            s.date_time,
-            s.market_price_for_hour.to_f64().unwrap(),
-        )
-    });
+    let max_val = max_contract.max(max_market);
 
     let sampler = args.sample.unwrap_or(1);
-    plotter::draw_png(&start, &end, max_val, set_series.step_by(sampler), market_series.step_by(sampler))?;
+    let mut contract_series = contract_names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let series = cumulative_series
+                .iter()
+                .step_by(sampler)
+                .map(|s| Ok((s.date_time, to_f64(s.cumulative_contract_prices[i])?)))
+                .collect::<Result<_, Box<dyn Error>>>()?;
+            Ok((name.clone(), series))
+        })
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+    let spot_contract_series = cumulative_series
+        .iter()
+        .step_by(sampler)
+        .map(|s| Ok((s.date_time, to_f64(s.cumulative_spot_contract_price)?)))
+        .collect::<Result<_, Box<dyn Error>>>()?;
+    contract_series.push((spot_contract_name, spot_contract_series));
+    let market_series = cumulative_series
+        .iter()
+        .map(|s| Ok((s.date_time, to_f64(s.cumulative_market_price)?)))
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
 
+    plotter::draw_png(
+        &start,
+        &end,
+        max_val,
+        contract_series,
+        market_series.into_iter().step_by(sampler),
+        &estimated,
+    )?;
 
-    write_csv(&cumulative_series)?;
+    write_csv(&contract_names, &cumulative_series)?;
     println!("Written image to test.png");
     Ok(())
 }
 
-fn write_csv(data: &[CumulativeComparisonData]) -> Result<(), Box<dyn Error>> {
+/// Cumulative price in euros as a chart coordinate
+fn to_f64(value: Decimal) -> Result<f64, Box<dyn Error>> {
+    value
+        .to_f64()
+        .ok_or_else(|| format!("{} can not be drawn", value).into())
+}
+
+/// Inputs of each file given on the command line
+type Sources = Vec<Vec<Input<'static>>>;
+
+/// Price client that tells the user about retried queries
+fn sahkotin_client() -> SahkotinClient {
+    SahkotinClient::default()
+        .with_retry_hook(|e, delay| eprintln!("Fetching prices failed ({e}), retrying in {delay:?}"))
+}
+
+/// Opens the inputs of each file given on the command line, oldest file first. Stdin is read as
+/// the newest.
+fn open_sources(args: &Cli) -> Result<Sources, Box<dyn Error>> {
+    let (stdin, patterns): (Vec<_>, Vec<_>) = args.file_paths.iter().partition(|p| *p == "-");
+    let files = InputFiles::expand(&patterns, args.duplicates)?;
+    let mut sources = files
+        .paths
+        .iter()
+        .map(|path| Input::open(path))
+        .collect::<Result<Vec<_>, _>>()?;
+    if !stdin.is_empty() {
+        sources.push(vec![Input::stdin()?]);
+    }
+    Ok(sources)
+}
+
+/// Detects the format from the first input, all files are expected to be of the same format
+fn detect_format(mut sources: Sources) -> Result<(FileFormat, Sources), Box<dyn Error>> {
+    let Some(inputs) = sources.iter_mut().find(|inputs| !inputs.is_empty()) else {
+        return Err("No input to read".into());
+    };
+    let (file_type, first) = FileFormat::detect_input(inputs.remove(0))?;
+    inputs.insert(0, first);
+    Ok((file_type, sources))
+}
+
+/// Comparison with the legend texts of the compared contracts
+struct Comparison {
+    spot_contract_name: String,
+    contract_names: Vec<String>,
+    data: Vec<CumulativeComparisonData>,
+    net_metering: Option<Vec<NetMeteringData>>,
+    /// Periods of estimated consumption to highlight in the chart
+    estimated: Vec<(DateTime<Utc>, DateTime<Utc>)>,
+}
+
+/// Compares the records in `sources`, merged, against the contracts given on the command line.
+fn compare<R>(
+    args: &Cli,
+    price_source: &dyn PriceSource,
+    sources: Sources,
+    start: &DateTime<Utc>,
+    end: &DateTime<Utc>,
+    timezone: Tz,
+) -> Result<Comparison, Box<dyn Error>>
+where
+    R: Record + Clone + DeserializeOwned + 'static,
+    eleparserlib::parser::Parser<R>: EnergyParser<R>,
+{
+    // both directions are kept for the net metering analysis
+    let loaded = read_merged::<R>(sources, args.duplicates, start, end, args.lenient, |_| true)?;
+    let records = loaded.records;
+
+    if args.lenient {
+        print_parse_report(&loaded.report);
+    }
+    if args.check_quality {
+        if loaded.duplicates > 0 {
+            println!("{} readings of overlapping files were merged", loaded.duplicates);
+        }
+        let selection = Selection::new(args.metering_point, Direction::Consumption);
+        let consumption = records.iter().filter(|r| selection.accepts(r));
+        print_quality_report(&QualityCheck::default().within(start, end).check(consumption));
+    }
+
+    match args.resolution {
+        Some(resolution) => {
+            let records = resample(records, resolution, args.incomplete_hours)?;
+            compare_records(args, price_source, records, start, end, timezone)
+        }
+        None => compare_records(args, price_source, records, start, end, timezone),
+    }
+}
+
+/// Compares merged `records` of one resolution against the contracts given on the command line
+fn compare_records<R>(
+    args: &Cli,
+    price_source: &dyn PriceSource,
+    records: Vec<R>,
+    start: &DateTime<Utc>,
+    end: &DateTime<Utc>,
+    timezone: Tz,
+) -> Result<Comparison, Box<dyn Error>>
+where
+    R: Record + Clone + 'static,
+{
+    let mut contracts: Vec<Box<dyn Contract<R>>> = vec![Box::new(FixedPrice::new(args.contract_price))];
+    contracts.extend(args.contracts.iter().map(|c| c.to_contract(timezone)));
+
+    let mut contract_names = contracts.iter().map(|c| c.name()).collect::<Vec<_>>();
+    if let Some(label) = &args.contract_label {
+        contract_names[0] = label.clone();
+    }
+
+    let spot_contract = SpotContract::spot(args.spot_margin, args.spot_monthly_fee, timezone);
+
+    let grid_costs = GridCosts {
+        network: args
+            .transfer
+            .as_ref()
+            .map(|t| t.to_network_tariff(timezone))
+            .transpose()?,
+        power: args
+            .power_tariff
+            .map(|price| PowerTariff::new(price, args.power_peak_hours, timezone)),
+        tax: args.electricity_tax.map(|class| ElectricityTax::new(class, args.vat)),
+    };
+
+    // split in one pass, production is only needed for the net metering analysis
+    let (consumption, production): (Vec<_>, Vec<_>) = records
+        .into_iter()
+        .partition(|r| r.direction() == Direction::Consumption);
+    let selection = Selection::new(args.metering_point, Direction::Consumption);
+    let consumption = selection.select(consumption)?;
+    print_estimated_shares(&consumption, args.check_quality, timezone);
+    let estimated = match args.estimated {
+        EstimatedReadings::Highlight => estimated_periods(&consumption)
+            .into_iter()
+            .map(|(from, to, _)| (from, to))
+            .collect(),
+        EstimatedReadings::Include | EstimatedReadings::Exclude => Vec::new(),
+    };
+    let (consumption, production) = match args.estimated {
+        EstimatedReadings::Exclude => (without_estimated(consumption), without_estimated(production)),
+        EstimatedReadings::Include | EstimatedReadings::Highlight => (consumption, production),
+    };
+    let feed_in = args.feed_in_margin.map(|margin| {
+        let records = consumption.iter().cloned().chain(production).collect::<Vec<_>>();
+        (margin, records)
+    });
+    let bins = eleparserlib::bins_within(consumption, start, end, timezone);
+    if let Some(power_tariff) = &grid_costs.power {
+        print_power_fees(&power_tariff.monthly_fees(&bins), timezone);
+    }
+
+    let pricing = Pricing {
+        spot_contract: &spot_contract,
+        contracts: &contracts,
+        grid_costs: &grid_costs,
+    };
+    let data = eleparserlib::compare_bins(price_source, &bins, start, end, &pricing)?;
+
+    let net_metering = match feed_in {
+        Some((margin, records)) => {
+            let production = Selection::new(args.feed_in_metering_point, Direction::Production);
+            let bins = net_bins(records, &selection, &production, start, end, timezone)?;
+            Some(net_metering(
+                price_source,
+                &bins,
+                start,
+                end,
+                &spot_contract,
+                &FeedIn::new(margin),
+                &grid_costs,
+            )?)
+        }
+        None => None,
+    };
+
+    Ok(Comparison {
+        spot_contract_name: Contract::<R>::name(&spot_contract),
+        contract_names,
+        data,
+        net_metering,
+        estimated,
+    })
+}
+
+/// Number of skipped rows listed before the rest are only counted
+const SHOWN_PARSE_ISSUES: usize = 20;
+
+fn print_parse_report(report: &ParseReport) {
+    if report.is_clean() {
+        return;
+    }
+    eprintln!(
+        "Skipped {} rows, parsed {}:",
+        report.issues.len(),
+        report.parsed_rows
+    );
+    for issue in report.issues.iter().take(SHOWN_PARSE_ISSUES) {
+        eprintln!("  {issue}");
+    }
+    if report.issues.len() > SHOWN_PARSE_ISSUES {
+        eprintln!("  ... and {} more", report.issues.len() - SHOWN_PARSE_ISSUES);
+    }
+}
+
+/// Number of quality issues listed before the rest are only counted
+const SHOWN_QUALITY_ISSUES: usize = 20;
+
+fn print_quality_report(report: &QualityReport) {
+    match (report.first, report.last) {
+        (Some(first), Some(last)) => println!(
+            "{} readings from {} to {}",
+            report.readings, first, last
+        ),
+        _ => println!("No readings to check"),
+    }
+    if report.is_clean() {
+        println!("No gaps, duplicates or unusual readings");
+        return;
+    }
+    println!(
+        "{} gaps with {} missing intervals, {} duplicated times, {} resolution changes, {} runs of zero, {} outliers, {} runs of estimated readings:",
+        report.gaps(),
+        report.missing_intervals(),
+        report.duplicates(),
+        report.resolution_changes(),
+        report.zero_runs(),
+        report.outliers(),
+        report.estimated_runs()
+    );
+    for issue in report.issues.iter().take(SHOWN_QUALITY_ISSUES) {
+        println!("  {issue}");
+    }
+    if report.issues.len() > SHOWN_QUALITY_ISSUES {
+        println!("  ... and {} more", report.issues.len() - SHOWN_QUALITY_ISSUES);
+    }
+}
+
+/// Prints the share of estimated consumption of each month, and of each day when `daily`. Prints
+/// nothing when all readings were measured.
+fn print_estimated_shares<R>(records: &[R], daily: bool, timezone: Tz)
+where
+    R: Record,
+{
+    let print = |shares: Vec<EstimatedShare>, format: &str| {
+        for share in shares.iter().filter(|s| !s.estimated.is_zero()) {
+            println!(
+                "{}: estimated {:.2} kWh of {:.2} kWh ({:.1} %)",
+                share.period.format(format),
+                share.estimated,
+                share.energy,
+                (share.share().unwrap_or_default() * Decimal::from(100)).round_dp(1)
+            );
+        }
+    };
+    let monthly = estimated_by_month(records, timezone);
+    if monthly.iter().all(|s| s.estimated.is_zero()) {
+        return;
+    }
+    print(monthly, "%Y-%m");
+    if daily {
+        print(estimated_by_day(records, timezone), "%Y-%m-%d");
+    }
+}
+
+fn print_power_fees(fees: &[MonthlyPowerFee], timezone: Tz) {
+    for fee in fees {
+        let hours = fee
+            .peak_hours
+            .iter()
+            .map(|(hour, energy)| {
+                let hour = hour.with_timezone(&timezone).format("%d.%m. %H:%M");
+                format!("{hour} {energy:.2} kW")
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        println!(
+            "{}-{:02}: peak demand {:.2} kW, power fee {:.2} € ({hours})",
+            fee.year, fee.month, fee.demand, fee.fee
+        );
+    }
+}
+
+fn print_net_metering(summary: &NetMeteringSummary) {
+    println!(
+        "Consumption {:.2} kWh, feed-in {:.2} kWh, purchased {:.2} kWh, sold {:.2} kWh",
+        summary.consumption, summary.feed_in, summary.purchased, summary.sold
+    );
+    if let Some(share) = summary.self_consumption_share {
+        println!("Self-consumption share of feed-in {:.1} %", share * Decimal::from(100));
+    }
+    println!(
+        "Purchase cost {:.2} €, export revenue {:.2} €, net cost {:.2} €",
+        summary.purchase_cost, summary.export_revenue, summary.net_cost
+    );
+}
+
+fn write_net_metering_csv(data: &[NetMeteringData]) -> Result<(), Box<dyn Error>> {
+    let mut writer = Writer::from_path("net_metering.csv")?;
+    for datum in data {
+        writer.serialize(datum)?;
+    }
+    Ok(())
+}
+
+fn write_csv(contract_names: &[String], data: &[CumulativeComparisonData]) -> Result<(), Box<dyn Error>> {
     let mut writer = Writer::from_path("comparison.csv")?;
+    let mut header = vec![
+        "date_time".to_string(),
+        "market_price_for_hour".to_string(),
+        "energy".to_string(),
+        "cumulative_market_price".to_string(),
+        "transfer_cost".to_string(),
+        "electricity_tax".to_string(),
+        "cumulative_spot_contract_price".to_string(),
+    ];
+    header.extend(contract_names.iter().cloned());
+    writer.write_record(&header)?;
     for datum in data {
-        writer.serialize(datum)?
+        let mut row = vec![
+            datum.date_time.to_rfc3339_opts(SecondsFormat::Secs, true),
+            datum.market_price_for_hour.to_string(),
+            datum.energy.to_string(),
+            datum.cumulative_market_price.to_string(),
+            datum.transfer_cost.to_string(),
+            datum.electricity_tax.to_string(),
+            datum.cumulative_spot_contract_price.to_string(),
+        ];
+        row.extend(datum.cumulative_contract_prices.iter().map(|p| p.to_string()));
+        writer.write_record(&row)?;
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use eleparserlib::priceclient::SpotPrice;
+
+    use super::*;
+
+    struct Prices;
+
+    impl PriceSource for Prices {
+        fn get_prices(
+            &self,
+            start: &DateTime<Utc>,
+            end: &DateTime<Utc>,
+        ) -> Result<Vec<SpotPrice>, eleparserlib::Error> {
+            let mut prices = Vec::new();
+            let mut time = *start;
+            while time <= *end {
+                prices.push(SpotPrice {
+                    time,
+                    price: Decimal::from(10),
+                    resolution: TimeResolution::PT1H,
+                });
+                time += chrono::Duration::hours(1);
+            }
+            Ok(prices)
+        }
+    }
+
+    #[test]
+    fn production_of_a_datahub_export_is_fed_in() {
+        let args = Cli::try_parse_from([
+            "eleparser",
+            "--file-path",
+            "export.csv",
+            "--start-date",
+            "2024-06-01",
+            "--feed-in-margin",
+            "1",
+        ])
+        .unwrap();
+        let data = "Mittauspisteen tunnus;Tuotteen tyyppi;Resoluutio;Yksikkötyyppi;Lukeman tyyppi;Alkuaika;Määrä;Laatu\n\
+            1;8716867000030;PT1H;kWh;BN01;2024-06-01T09:00:00Z;0,5;OK\n\
+            2;8716867000030;PT1H;kWh;BN02;2024-06-01T09:00:00Z;2,0;OK\n\
+            1;8716867000030;PT1H;kWh;BN01;2024-06-01T10:00:00Z;1,5;OK\n";
+        let sources = vec![vec![Input::new("export.csv", Cursor::new(data.as_bytes()))]];
+        let (file_type, sources) = detect_format(sources).unwrap();
+        assert_eq!(file_type, FileFormat::Fingrid);
+
+        let start = Utc.ymd(2024, 6, 1).and_hms(0, 0, 0);
+        let end = Utc.ymd(2024, 6, 2).and_hms(0, 0, 0);
+        let comparison = compare::<FingridRecord>(&args, &Prices, sources, &start, &end, Helsinki).unwrap();
+        let summary = NetMeteringSummary::new(&comparison.net_metering.unwrap());
+
+        assert_eq!((summary.consumption, summary.feed_in), (Decimal::from(2), Decimal::from(2)));
+        assert_eq!(summary.sold, Decimal::new(15, 1));
+    }
+}
@@ -0,0 +1,254 @@
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+
+use crate::channel::Channel;
+use crate::error::Error;
+use crate::record::{ReadingQuality, Record};
+
+/// Extensions of the files read from a directory
+const SUPPORTED_EXTENSIONS: [&str; 3] = ["csv", "gz", "zip"];
+
+/// How to resolve two readings of the same metering point, direction and time
+#[derive(clap::ValueEnum, Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Keep a measured reading over an estimated one, otherwise the one from the newest file
+    #[default]
+    PreferMeasured,
+    /// Keep the reading from the newest file
+    PreferNewest,
+    /// Fail when the readings differ
+    Error,
+}
+
+impl DuplicatePolicy {
+    /// Whether `new`, from the same or a newer file, replaces `old`
+    fn replaces<R>(&self, old: &R, new: &R) -> Result<bool, Error>
+    where
+        R: Record,
+    {
+        let measured = |r: &R| r.quality() == Some(ReadingQuality::Measured);
+        match self {
+            DuplicatePolicy::PreferMeasured if measured(old) != measured(new) => Ok(measured(new)),
+            DuplicatePolicy::PreferMeasured | DuplicatePolicy::PreferNewest => Ok(true),
+            DuplicatePolicy::Error if old.energy() == new.energy() => Ok(false),
+            DuplicatePolicy::Error => Err(Error::Duplicate {
+                time: new.date_time(),
+                metering_point: new.metering_point(),
+            }),
+        }
+    }
+}
+
+/// Files to read and how readings in overlapping files are resolved
+#[derive(Debug, Clone)]
+pub struct InputFiles {
+    /// Oldest first
+    pub paths: Vec<PathBuf>,
+    pub duplicates: DuplicatePolicy,
+}
+
+impl InputFiles {
+    pub fn new(paths: Vec<PathBuf>, duplicates: DuplicatePolicy) -> Self {
+        InputFiles { paths, duplicates }
+    }
+
+    /// Expands `patterns` into files ordered by modification time, oldest first. A pattern can be
+    /// a file, a directory whose `.csv`, `.gz` and `.zip` files are read, or a glob.
+    pub fn expand(patterns: &[impl AsRef<str>], duplicates: DuplicatePolicy) -> Result<Self, Error> {
+        let mut paths = Vec::new();
+        for pattern in patterns {
+            paths.extend(expand_pattern(pattern.as_ref())?);
+        }
+
+        let mut modified = Vec::with_capacity(paths.len());
+        for path in paths {
+            let time = fs::metadata(&path)
+                .and_then(|m| m.modified())
+                .map_err(|e| Error::io(&path, e))?;
+            modified.push((time, path));
+        }
+        // stable, so files of the same age stay in the given order
+        modified.sort_by_key(|(time, _)| *time);
+        Ok(InputFiles::new(
+            modified.into_iter().map(|(_, path)| path).collect(),
+            duplicates,
+        ))
+    }
+}
+
+fn expand_pattern(pattern: &str) -> Result<Vec<PathBuf>, Error> {
+    let path = Path::new(pattern);
+    if path.is_dir() {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(path).map_err(|e| Error::io(path, e))? {
+            let file = entry.map_err(|e| Error::io(path, e))?.path();
+            let supported = file
+                .extension()
+                .map(|e| SUPPORTED_EXTENSIONS.contains(&e.to_string_lossy().to_lowercase().as_str()))
+                .unwrap_or(false);
+            if file.is_file() && supported {
+                files.push(file);
+            }
+        }
+        files.sort();
+        return Ok(files);
+    }
+    if path.exists() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let matches = glob::glob(pattern)
+        .map_err(|e| Error::io(path, io::Error::new(io::ErrorKind::InvalidInput, e)))?
+        .map(|m| m.map_err(|e| Error::io(path, io::Error::from(e))))
+        .collect::<Result<Vec<_>, _>>()?;
+    if matches.is_empty() {
+        return Err(Error::io(
+            path,
+            io::Error::new(io::ErrorKind::NotFound, "no such file or matching files"),
+        ));
+    }
+    Ok(matches)
+}
+
+/// Merges the records of several sources, oldest first, into one series ordered by time. Readings
+/// of the same metering point, direction and time are resolved with `policy`, so overlapping
+/// downloads are not counted twice.
+///
+/// The readings are keyed by time and channel, so a source does not need to be in time order and
+/// can list its metering points and directions one after another, like the exports do.
+pub fn merge<R, S>(sources: impl IntoIterator<Item = S>, policy: DuplicatePolicy) -> Result<Merged<R>, Error>
+where
+    R: Record,
+    S: IntoIterator<Item = Result<R, Error>>,
+{
+    let mut merged: BTreeMap<Key, R> = BTreeMap::new();
+    let mut duplicates = 0;
+    for source in sources {
+        for record in source {
+            let record = record?;
+            match merged.entry(key(&record)) {
+                Entry::Vacant(entry) => {
+                    entry.insert(record);
+                }
+                Entry::Occupied(mut entry) => {
+                    duplicates += 1;
+                    if policy.replaces(entry.get(), &record)? {
+                        entry.insert(record);
+                    }
+                }
+            }
+        }
+    }
+    Ok(Merged {
+        records: merged.into_values().collect(),
+        duplicates,
+    })
+}
+
+type Key = (DateTime<Utc>, Channel);
+
+fn key<R>(record: &R) -> Key
+where
+    R: Record,
+{
+    (record.date_time(), Channel::of(record))
+}
+
+/// Records returned by [`merge`]
+#[derive(Debug, Clone)]
+pub struct Merged<R> {
+    /// Ordered by time, metering point and direction
+    pub records: Vec<R>,
+    /// Number of readings dropped because another reading of the same time was kept
+    pub duplicates: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Timelike};
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::record::fingrid::FingridRecord;
+    use crate::record::{Direction, TimeResolution};
+
+    fn reading(hour: u32, energy: i64, quality: ReadingQuality) -> Result<FingridRecord, Error> {
+        reading_of(1, hour, energy, quality)
+    }
+
+    fn reading_of(metering_point: u64, hour: u32, energy: i64, quality: ReadingQuality) -> Result<FingridRecord, Error> {
+        Ok(FingridRecord {
+            metering_point: Some(metering_point),
+            product_type: None,
+            resolution: TimeResolution::PT1H,
+            unit: None,
+            direction: Direction::Consumption,
+            date_time: Utc.ymd(2022, 1, 1).and_hms(hour, 0, 0),
+            energy: Decimal::from(energy),
+            quality: Some(quality),
+        })
+    }
+
+    fn energies(records: &[FingridRecord]) -> Vec<Decimal> {
+        records.iter().map(|r| r.energy).collect()
+    }
+
+    #[test]
+    fn merges_overlapping_sources_in_time_order() {
+        let old = vec![reading(1, 1, ReadingQuality::Measured), reading(2, 2, ReadingQuality::Measured)];
+        let new = vec![reading(2, 3, ReadingQuality::Estimated), reading(3, 4, ReadingQuality::Measured)];
+        let merged = merge([old, new], DuplicatePolicy::PreferMeasured).unwrap();
+
+        assert_eq!(energies(&merged.records), vec![Decimal::from(1), Decimal::from(2), Decimal::from(4)]);
+        assert_eq!(merged.duplicates, 1);
+    }
+
+    #[test]
+    fn prefers_the_newest_source() {
+        let old = vec![reading(1, 1, ReadingQuality::Measured)];
+        let new = vec![reading(1, 2, ReadingQuality::Measured)];
+        let merged = merge([old, new], DuplicatePolicy::PreferNewest).unwrap();
+
+        assert_eq!(energies(&merged.records), vec![Decimal::from(2)]);
+    }
+
+    #[test]
+    fn fails_on_conflicting_readings() {
+        let old = vec![reading(1, 1, ReadingQuality::Measured)];
+        let new = vec![reading(1, 2, ReadingQuality::Measured)];
+        let merged = merge([old, new], DuplicatePolicy::Error);
+
+        assert!(matches!(merged, Err(Error::Duplicate { .. })));
+    }
+
+    #[test]
+    fn merges_a_source_grouped_by_metering_point() {
+        let old = vec![
+            reading_of(1, 1, 1, ReadingQuality::Measured),
+            reading_of(1, 2, 2, ReadingQuality::Measured),
+            reading_of(2, 1, 10, ReadingQuality::Measured),
+            reading_of(2, 2, 20, ReadingQuality::Measured),
+        ];
+        let new = vec![
+            reading_of(2, 2, 30, ReadingQuality::Measured),
+            reading_of(2, 3, 40, ReadingQuality::Measured),
+            reading_of(1, 3, 3, ReadingQuality::Measured),
+        ];
+        let merged = merge([old, new], DuplicatePolicy::PreferNewest).unwrap();
+
+        let readings = merged
+            .records
+            .iter()
+            .map(|r| (r.date_time.hour(), r.metering_point, r.energy))
+            .collect::<Vec<_>>();
+        let expected = [(1, 1, 1), (1, 2, 10), (2, 1, 2), (2, 2, 30), (3, 1, 3), (3, 2, 40)]
+            .map(|(hour, metering_point, energy)| (hour, Some(metering_point), Decimal::from(energy)));
+        assert_eq!(readings, expected);
+        assert_eq!(merged.duplicates, 1);
+    }
+}
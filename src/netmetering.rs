@@ -0,0 +1,324 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::bins::Bins;
+use crate::channel::Selection;
+use crate::contract::{Contract, SpotContract};
+use crate::error::Error;
+use crate::priceclient::PriceSource;
+use crate::record::{Direction, Record, RecordWithPrice, TimeResolution};
+use crate::tariff::GridCosts;
+use crate::{bins_within, with_prices};
+
+/// Consumption and grid feed-in of one metering interval, netted against each other. As a
+/// [`Record`] its energy is the energy bought from the grid after netting.
+#[derive(Debug, Copy, Clone)]
+pub struct NetRecord {
+    pub date_time: DateTime<Utc>,
+    pub resolution: TimeResolution,
+    /// Energy taken from the grid in kWh
+    pub consumption: Decimal,
+    /// Energy fed into the grid in kWh
+    pub feed_in: Decimal,
+}
+
+impl NetRecord {
+    /// Energy bought after netting
+    pub fn purchased(&self) -> Decimal {
+        (self.consumption - self.feed_in).max(Decimal::ZERO)
+    }
+
+    /// Energy sold after netting
+    pub fn sold(&self) -> Decimal {
+        (self.feed_in - self.consumption).max(Decimal::ZERO)
+    }
+}
+
+impl Record for NetRecord {
+    fn resolution(&self) -> TimeResolution {
+        self.resolution
+    }
+
+    fn date_time(&self) -> DateTime<Utc> {
+        self.date_time
+    }
+
+    fn energy(&self) -> Decimal {
+        self.purchased()
+    }
+
+    fn temperature(&self) -> Option<f32> {
+        None
+    }
+}
+
+/// Pairs consumption and production records by interval. An interval with readings in only one
+/// direction has zero energy in the other.
+pub fn pair_records<R>(records: impl IntoIterator<Item = R>) -> Vec<NetRecord>
+where
+    R: Record,
+{
+    let mut intervals: BTreeMap<DateTime<Utc>, NetRecord> = BTreeMap::new();
+    for record in records {
+        let interval = intervals.entry(record.date_time()).or_insert(NetRecord {
+            date_time: record.date_time(),
+            resolution: record.resolution(),
+            consumption: Decimal::ZERO,
+            feed_in: Decimal::ZERO,
+        });
+        match record.direction() {
+            Direction::Consumption => interval.consumption += record.energy(),
+            Direction::Production => interval.feed_in += record.energy(),
+            Direction::Unknown => {}
+        }
+    }
+    intervals.into_values().collect()
+}
+
+/// Pairs the `consumption` and `production` records within the given time range and bins them by
+/// days in `timezone`. In Datahub the production usually has a metering point of its own.
+pub fn net_bins<R>(
+    records: Vec<R>,
+    consumption: &Selection,
+    production: &Selection,
+    start_time: &DateTime<Utc>,
+    end_time: &DateTime<Utc>,
+    timezone: Tz,
+) -> Result<Bins<NetRecord>, Error>
+where
+    R: Record,
+{
+    let (consumed, produced): (Vec<_>, Vec<_>) = records
+        .into_iter()
+        .partition(|r| r.direction() == consumption.direction);
+    let consumption = consumption.select(consumed)?;
+    let production = production.select(produced)?;
+    let paired = pair_records(consumption.into_iter().chain(production));
+    Ok(bins_within(paired, start_time, end_time, timezone))
+}
+
+/// Feed-in contract that pays the spot price minus the seller's margin, in c/kWh
+#[derive(Debug, Copy, Clone)]
+pub struct FeedIn {
+    pub margin: Decimal,
+}
+
+impl FeedIn {
+    pub fn new(margin: Decimal) -> Self {
+        FeedIn { margin }
+    }
+
+    /// Revenue of the energy sold in `record` in cents. Negative when the spot price is below the
+    /// margin.
+    pub fn revenue(&self, record: &RecordWithPrice<NetRecord>) -> Decimal {
+        (record.price - self.margin) * record.record.sold()
+    }
+}
+
+/// Cumulative costs and revenue of a household with grid feed-in, in euros
+#[derive(Debug, Clone, Serialize)]
+pub struct NetMeteringData {
+    pub date_time: DateTime<Utc>,
+    pub market_price_for_hour: Decimal,
+    pub consumption: Decimal,
+    pub feed_in: Decimal,
+    pub purchased: Decimal,
+    pub sold: Decimal,
+    /// Spot contract and grid costs of the purchased energy
+    pub cumulative_purchase_cost: Decimal,
+    pub cumulative_export_revenue: Decimal,
+    pub cumulative_net_cost: Decimal,
+}
+
+/// Totals of a net metering analysis, energy in kWh and money in euros
+#[derive(Debug, Default, Copy, Clone)]
+pub struct NetMeteringSummary {
+    pub consumption: Decimal,
+    pub feed_in: Decimal,
+    pub purchased: Decimal,
+    pub sold: Decimal,
+    /// Share of the fed-in energy that was consumed in the same interval and netted away, `None`
+    /// without feed-in. Production used behind the meter is not metered and so not included.
+    pub self_consumption_share: Option<Decimal>,
+    pub purchase_cost: Decimal,
+    pub export_revenue: Decimal,
+    pub net_cost: Decimal,
+}
+
+impl NetMeteringSummary {
+    pub fn new(data: &[NetMeteringData]) -> Self {
+        let sum = |f: fn(&NetMeteringData) -> Decimal| data.iter().map(f).sum::<Decimal>();
+        let feed_in = sum(|d| d.feed_in);
+        let sold = sum(|d| d.sold);
+        let last = data.last();
+        NetMeteringSummary {
+            consumption: sum(|d| d.consumption),
+            feed_in,
+            purchased: sum(|d| d.purchased),
+            sold,
+            self_consumption_share: (!feed_in.is_zero()).then(|| (feed_in - sold) / feed_in),
+            purchase_cost: last.map(|d| d.cumulative_purchase_cost).unwrap_or_default(),
+            export_revenue: last.map(|d| d.cumulative_export_revenue).unwrap_or_default(),
+            net_cost: last.map(|d| d.cumulative_net_cost).unwrap_or_default(),
+        }
+    }
+}
+
+/// Prices the netted records in `bins`: purchased energy with `spot_contract` and `grid_costs`,
+/// sold energy with `feed_in`
+pub fn net_metering<P>(
+    price_source: &P,
+    bins: &Bins<NetRecord>,
+    start_time: &DateTime<Utc>,
+    end_time: &DateTime<Utc>,
+    spot_contract: &SpotContract,
+    feed_in: &FeedIn,
+    grid_costs: &GridCosts,
+) -> Result<Vec<NetMeteringData>, Error>
+where
+    P: PriceSource + ?Sized,
+{
+    let records = with_prices(price_source, start_time, end_time, bins)?;
+    let purchase_costs = spot_contract.costs(&records);
+    let grid_costs = grid_costs.costs(&records);
+
+    let mut purchase_sum = Decimal::ZERO;
+    let mut revenue_sum = Decimal::ZERO;
+    let data = records
+        .iter()
+        .enumerate()
+        .map(|(i, r)| {
+            purchase_sum += purchase_costs[i] + grid_costs[i].total();
+            revenue_sum += feed_in.revenue(r);
+            NetMeteringData {
+                date_time: r.record.date_time,
+                market_price_for_hour: r.price,
+                consumption: r.record.consumption,
+                feed_in: r.record.feed_in,
+                purchased: r.record.purchased(),
+                sold: r.record.sold(),
+                cumulative_purchase_cost: purchase_sum / Decimal::from(100),
+                cumulative_export_revenue: revenue_sum / Decimal::from(100),
+                cumulative_net_cost: (purchase_sum - revenue_sum) / Decimal::from(100),
+            }
+        })
+        .collect();
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use chrono_tz::Europe::Helsinki;
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::parser::{EnergyParser, FingridParser};
+    use crate::priceclient::SpotPrice;
+    use crate::record::fingrid::FingridRecord;
+
+    fn reading(metering_point: u64, direction: Direction, hour: u32, energy: Decimal) -> FingridRecord {
+        FingridRecord {
+            metering_point: Some(metering_point),
+            product_type: None,
+            resolution: TimeResolution::PT1H,
+            unit: None,
+            direction,
+            date_time: Utc.ymd(2024, 6, 1).and_hms(hour, 0, 0),
+            energy,
+            quality: None,
+        }
+    }
+
+    #[test]
+    fn pairs_consumption_and_production_of_the_same_interval() {
+        let paired = pair_records([
+            reading(1, Direction::Consumption, 9, dec!(0.5)),
+            reading(2, Direction::Production, 9, dec!(2.0)),
+            reading(1, Direction::Consumption, 10, dec!(1.5)),
+            reading(3, Direction::Unknown, 10, dec!(9.0)),
+        ]);
+
+        assert_eq!(paired.len(), 2);
+        assert_eq!((paired[0].consumption, paired[0].feed_in), (dec!(0.5), dec!(2.0)));
+        assert_eq!((paired[0].purchased(), paired[0].sold()), (Decimal::ZERO, dec!(1.5)));
+        // no production reading
+        assert_eq!((paired[1].consumption, paired[1].feed_in), (dec!(1.5), Decimal::ZERO));
+        assert_eq!((paired[1].purchased(), paired[1].sold()), (dec!(1.5), Decimal::ZERO));
+    }
+
+    #[test]
+    fn feed_in_of_a_datahub_export_is_read_from_its_production_metering_point() {
+        let data = "Mittauspisteen tunnus;Tuotteen tyyppi;Resoluutio;Yksikkötyyppi;Lukeman tyyppi;Alkuaika;Määrä;Laatu\n\
+            1;8716867000030;PT1H;kWh;BN01;2024-06-01T09:00:00Z;0,5;OK\n\
+            2;8716867000030;PT1H;kWh;BN02;2024-06-01T09:00:00Z;2,0;OK\n\
+            1;8716867000030;PT1H;kWh;BN01;2024-06-01T10:00:00Z;1,5;OK\n";
+        let records = FingridParser::parse_bytes("export.csv", data.as_bytes()).unwrap();
+        let bins = net_bins(
+            records,
+            &Selection::new(None, Direction::Consumption),
+            &Selection::new(None, Direction::Production),
+            &Utc.ymd(2024, 6, 1).and_hms(0, 0, 0),
+            &Utc.ymd(2024, 6, 2).and_hms(0, 0, 0),
+            Helsinki,
+        )
+        .unwrap();
+
+        let records = bins.bins.iter().flat_map(|bin| bin.records()).collect::<Vec<_>>();
+        let feed_in = records.iter().map(|r| r.feed_in).sum::<Decimal>();
+        let consumption = records.iter().map(|r| r.consumption).sum::<Decimal>();
+        assert_eq!((consumption, feed_in), (dec!(2.0), dec!(2.0)));
+    }
+
+    struct Prices(Decimal);
+
+    impl PriceSource for Prices {
+        fn get_prices(&self, start: &DateTime<Utc>, end: &DateTime<Utc>) -> Result<Vec<SpotPrice>, Error> {
+            let mut prices = Vec::new();
+            let mut time = *start;
+            while time <= *end {
+                prices.push(SpotPrice {
+                    time,
+                    price: self.0,
+                    resolution: TimeResolution::PT1H,
+                });
+                time += chrono::Duration::hours(1);
+            }
+            Ok(prices)
+        }
+    }
+
+    #[test]
+    fn sold_energy_earns_the_spot_price_minus_the_margin() {
+        let records = pair_records([
+            reading(1, Direction::Consumption, 9, dec!(0.5)),
+            reading(2, Direction::Production, 9, dec!(2.0)),
+            reading(1, Direction::Consumption, 10, dec!(1.5)),
+        ]);
+        let start = Utc.ymd(2024, 6, 1).and_hms(0, 0, 0);
+        let end = Utc.ymd(2024, 6, 2).and_hms(0, 0, 0);
+        let bins = bins_within(records, &start, &end, Helsinki);
+        let data = net_metering(
+            &Prices(Decimal::from(10)),
+            &bins,
+            &start,
+            &end,
+            &SpotContract::spot(Decimal::ZERO, Decimal::ZERO, Helsinki),
+            &FeedIn::new(Decimal::ONE),
+            &GridCosts::default(),
+        )
+        .unwrap();
+        let summary = NetMeteringSummary::new(&data);
+
+        assert_eq!((summary.purchased, summary.sold), (dec!(1.5), dec!(1.5)));
+        // 1.5 kWh at 10 c/kWh bought and at 9 c/kWh sold
+        assert_eq!(summary.purchase_cost, dec!(0.15));
+        assert_eq!(summary.export_revenue, dec!(0.135));
+        assert_eq!(summary.net_cost, dec!(0.015));
+        assert_eq!(summary.self_consumption_share, Some(dec!(0.25)));
+    }
+}
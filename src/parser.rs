@@ -1,14 +1,125 @@
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::io::Read;
 use std::marker::PhantomData;
-use std::path::Path;
-use serde::Deserialize;
+use std::path::{Path, PathBuf};
 
+use chrono::{DateTime, Utc};
+use serde::de::{DeserializeOwned, DeserializeSeed, MapAccess, Visitor};
+use serde::{forward_to_deserialize_any, Deserialize, Deserializer};
+
+use crate::error::Error;
+use crate::input::Input;
 use crate::record::fingrid::FingridRecord;
 use crate::record::oomi::OomiRecord;
 use crate::record::Record;
 pub trait EnergyParser<T>
-where T: Record
+where T: Record + DeserializeOwned
 {
-    fn parse(file_path: &Path) -> Vec<T>;
+    /// Reads the records of `inputs` lazily one after another. In lenient mode rows that can not
+    /// be parsed are skipped and listed in [`Records::report`], otherwise the first bad row is
+    /// returned as an error.
+    fn read<'a>(inputs: Vec<Input<'a>>, lenient: bool) -> Records<'a, T>;
+
+    /// Reads the records of a file lazily, failing on the first bad row. Compressed files are
+    /// handled like in [`Input::open`].
+    fn records(file_path: &Path) -> Result<Records<'static, T>, Error> {
+        Ok(Self::read(Input::open(file_path)?, false))
+    }
+
+    /// Reads the records of a file lazily, skipping rows that can not be parsed
+    fn records_lenient(file_path: &Path) -> Result<Records<'static, T>, Error> {
+        Ok(Self::read(Input::open(file_path)?, true))
+    }
+
+    /// Reads the records from any reader lazily, `name` is used in error messages
+    fn records_from_reader<'a>(name: &str, reader: impl Read + 'a, lenient: bool) -> Records<'a, T> {
+        Self::read(vec![Input::new(name, reader)], lenient)
+    }
+
+    fn parse(file_path: &Path) -> Result<Vec<T>, Error> {
+        Self::records(file_path)?.collect()
+    }
+
+    /// Parses an export from any reader, e.g. stdin, `name` is used in error messages
+    fn parse_reader(name: &str, reader: impl Read) -> Result<Vec<T>, Error> {
+        Self::records_from_reader(name, reader, false).collect()
+    }
+
+    /// Parses an export already in memory, e.g. an uploaded file
+    fn parse_bytes(name: &str, data: &[u8]) -> Result<Vec<T>, Error> {
+        Self::parse_reader(name, data)
+    }
+
+    /// Parses the rows that can be parsed and reports the rest instead of failing on the first
+    /// bad row. Only errors that prevent reading the file at all are returned as `Err`.
+    fn parse_lenient(file_path: &Path) -> Result<(Vec<T>, ParseReport), Error> {
+        let mut records = Self::records_lenient(file_path)?;
+        let parsed = records.by_ref().collect::<Result<Vec<_>, _>>()?;
+        Ok((parsed, records.into_report()))
+    }
+}
+
+/// A row that was skipped in lenient mode
+#[derive(Debug, Clone)]
+pub struct ParseIssue {
+    pub file: PathBuf,
+    pub line: Option<u64>,
+    /// Header of the column that could not be parsed, when the problem is in a single cell
+    pub column: Option<String>,
+    /// The offending cell, or the whole row when the column is not known
+    pub raw_value: String,
+    pub message: String,
+}
+
+impl ParseIssue {
+    /// A row or input that could not be read at all
+    fn unreadable(file: &Path, error: &csv::Error) -> Self {
+        ParseIssue {
+            file: file.to_path_buf(),
+            line: error.position().map(|p| p.line()),
+            column: None,
+            raw_value: String::new(),
+            message: error.to_string(),
+        }
+    }
+}
+
+impl Display for ParseIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.file.display())?;
+        if let Some(line) = self.line {
+            write!(f, " line {}", line)?;
+        }
+        if let Some(column) = &self.column {
+            write!(f, " column '{}'", column)?;
+        }
+        write!(f, ": {}", self.message)?;
+        if !self.raw_value.is_empty() {
+            write!(f, " (value '{}')", self.raw_value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Rows skipped while parsing in lenient mode
+#[derive(Debug, Clone, Default)]
+pub struct ParseReport {
+    /// Number of rows that were parsed successfully
+    pub parsed_rows: usize,
+    pub issues: Vec<ParseIssue>,
+}
+
+impl ParseReport {
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// Combines the reports of several files
+    pub fn merge(&mut self, other: ParseReport) {
+        self.parsed_rows += other.parsed_rows;
+        self.issues.extend(other.issues);
+    }
 }
 pub struct Parser<T>
 {
@@ -19,42 +130,316 @@ pub type FingridParser = Parser<FingridRecord>;
 pub type OomiParser = Parser<OomiRecord>;
 
 impl EnergyParser<FingridRecord> for Parser<FingridRecord> {
+    fn read<'a>(inputs: Vec<Input<'a>>, lenient: bool) -> Records<'a, FingridRecord> {
+        Records::new(inputs, lenient)
+    }
+}
+
+impl EnergyParser<OomiRecord> for Parser<OomiRecord> {
+    fn read<'a>(inputs: Vec<Input<'a>>, lenient: bool) -> Records<'a, OomiRecord> {
+        Records::new(inputs, lenient)
+    }
+}
+
+/// Semicolon separated input being read
+struct CurrentInput<'a> {
+    name: PathBuf,
+    reader: csv::Reader<Box<dyn Read + 'a>>,
+    headers: csv::StringRecord,
+}
+
+/// Records of semicolon separated exports with a header row, read one row at a time
+pub struct Records<'a, T> {
+    inputs: VecDeque<Input<'a>>,
+    current: Option<CurrentInput<'a>>,
+    row: csv::StringRecord,
+    lenient: bool,
+    range: Option<(DateTime<Utc>, DateTime<Utc>)>,
+    report: ParseReport,
+    _record: PhantomData<T>,
+}
+
+impl<'a, T> Records<'a, T>
+where
+    T: Record + DeserializeOwned,
+{
+    fn new(inputs: Vec<Input<'a>>, lenient: bool) -> Self {
+        Records {
+            inputs: inputs.into(),
+            current: None,
+            row: csv::StringRecord::new(),
+            lenient,
+            range: None,
+            report: ParseReport::default(),
+            _record: PhantomData,
+        }
+    }
 
-    fn parse(file_path: &Path) -> Vec<FingridRecord> {
+    /// Skips records outside `start_time..=end_time` while reading
+    pub fn within(mut self, start_time: &DateTime<Utc>, end_time: &DateTime<Utc>) -> Self {
+        self.range = Some((*start_time, *end_time));
+        self
+    }
+
+    /// Rows skipped so far in lenient mode
+    pub fn report(&self) -> &ParseReport {
+        &self.report
+    }
+
+    pub fn into_report(self) -> ParseReport {
+        self.report
+    }
+
+    fn in_range(&self, record: &T) -> bool {
+        match self.range {
+            Some((start, end)) => record.date_time() >= start && record.date_time() <= end,
+            None => true,
+        }
+    }
+
+    /// Starts reading the next input, or skips it in lenient mode when its header can not be read.
+    /// In lenient mode rows with missing cells, e.g. a truncated
+    /// last row, are reported too.
+    fn open_next(&mut self) -> Option<Result<(), Error>> {
+        let input = self.inputs.pop_front()?;
         let mut reader = csv::ReaderBuilder::new()
             .delimiter(b';')
             .has_headers(true)
-            .from_path(file_path)
-            .unwrap();
+            .flexible(self.lenient)
+            .from_reader(input.reader);
+        let headers = match reader.headers() {
+            Ok(headers) => headers.clone(),
+            Err(e) if self.lenient => {
+                self.report.issues.push(ParseIssue::unreadable(&input.name, &e));
+                return Some(Ok(()));
+            }
+            Err(e) => return Some(Err(Error::csv(&input.name, e))),
+        };
+        self.current = Some(CurrentInput {
+            name: input.name,
+            reader,
+            headers,
+        });
+        Some(Ok(()))
+    }
+}
+
+impl<T> Iterator for Records<'_, T>
+where
+    T: Record + DeserializeOwned,
+{
+    type Item = Result<T, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let Some(current) = &mut self.current else {
+                match self.open_next()? {
+                    Ok(()) => continue,
+                    Err(e) => return Some(Err(e)),
+                }
+            };
 
-        let records: Vec<_> = reader
-            .deserialize()
-            .map(|r| {
-                let record: FingridRecord = r.unwrap();
-                record
-            })
-            .collect();
+            match current.reader.read_record(&mut self.row) {
+                Ok(true) => {}
+                Ok(false) => {
+                    self.current = None;
+                    continue;
+                }
+                Err(e) if self.lenient => {
+                    let issue = ParseIssue::unreadable(&current.name, &e);
+                    // the rest of an input that can not be read is skipped
+                    if e.is_io_error() {
+                        self.current = None;
+                    }
+                    self.report.issues.push(issue);
+                    continue;
+                }
+                Err(e) => return Some(Err(Error::csv(&current.name, e))),
+            }
 
-        records
+            match self.row.deserialize::<T>(Some(&current.headers)) {
+                Ok(record) => {
+                    self.report.parsed_rows += 1;
+                    if self.in_range(&record) {
+                        return Some(Ok(record));
+                    }
+                }
+                Err(e) if self.lenient => {
+                    let issue = row_issue::<T>(&current.name, &current.headers, &self.row, e);
+                    self.report.issues.push(issue);
+                }
+                Err(e) => return Some(Err(Error::csv(&current.name, e))),
+            }
+        }
     }
 }
 
-impl EnergyParser<OomiRecord> for Parser<OomiRecord> {
-    fn parse(file_path: &Path) -> Vec<OomiRecord> {
-        let mut reader = csv::ReaderBuilder::new()
-            .delimiter(b';')
-            .has_headers(true)
-            .from_path(file_path)
-            .unwrap();
-
-        let records: Vec<_> = reader
-            .deserialize()
-            .map(|r| {
-                let record: OomiRecord = r.unwrap();
-                record
-            })
-            .collect();
-
-        records
+fn row_issue<T>(
+    file_path: &Path,
+    headers: &csv::StringRecord,
+    row: &csv::StringRecord,
+    error: csv::Error,
+) -> ParseIssue
+where
+    T: DeserializeOwned,
+{
+    let (field, message) = match error.kind() {
+        csv::ErrorKind::Deserialize { err, .. } => {
+            (err.field().map(|f| f as usize), err.kind().to_string())
+        }
+        _ => (None, error.to_string()),
+    };
+    // errors raised by our own deserializers do not know their field
+    let field = field
+        .or_else(|| row.deserialize::<FailingColumn<T>>(Some(headers)).ok().and_then(|f| f.index))
+        .or_else(|| (row.len() < headers.len()).then_some(row.len()));
+
+    match field {
+        Some(field) => ParseIssue {
+            file: file_path.to_path_buf(),
+            line: error.position().or(row.position()).map(|p| p.line()),
+            column: headers.get(field).map(str::to_string),
+            raw_value: row.get(field).unwrap_or_default().to_string(),
+            message,
+        },
+        None => ParseIssue {
+            file: file_path.to_path_buf(),
+            line: error.position().or(row.position()).map(|p| p.line()),
+            column: None,
+            raw_value: row.iter().collect::<Vec<_>>().join(";"),
+            message,
+        },
+    }
+}
+
+/// Index of the column whose value fails to deserialize as `T`, found by deserializing the row
+/// again and counting the columns that were read before the failure
+struct FailingColumn<T> {
+    index: Option<usize>,
+    _record: PhantomData<T>,
+}
+
+impl<'de, T> Deserialize<'de> for FailingColumn<T>
+where
+    T: DeserializeOwned,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let mut index = None;
+        let counting = Counting { inner: deserializer, failed: &mut index };
+        // the error itself was already reported, only the column is of interest here
+        let _ = T::deserialize(counting);
+        Ok(FailingColumn { index, _record: PhantomData })
+    }
+}
+
+/// Wraps the deserializer, visitor and map of a row to find the failing column
+struct Counting<'f, I> {
+    inner: I,
+    failed: &'f mut Option<usize>,
+}
+
+impl<'de, D> Deserializer<'de> for Counting<'_, D>
+where
+    D: Deserializer<'de>,
+{
+    type Error = D::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.inner.deserialize_any(Counting { inner: visitor, failed: self.failed })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.inner.deserialize_struct(name, fields, Counting { inner: visitor, failed: self.failed })
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option
+        unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier ignored_any
+    }
+}
+
+impl<'de, V> Visitor<'de> for Counting<'_, V>
+where
+    V: Visitor<'de>,
+{
+    type Value = V::Value;
+
+    fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        self.inner.expecting(formatter)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+        self.inner.visit_map(CountingMap { inner: map, keys: 0, failed: self.failed })
+    }
+}
+
+struct CountingMap<'f, A> {
+    inner: A,
+    keys: usize,
+    failed: &'f mut Option<usize>,
+}
+
+impl<'de, A> MapAccess<'de> for CountingMap<'_, A>
+where
+    A: MapAccess<'de>,
+{
+    type Error = A::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error> {
+        let key = self.inner.next_key_seed(seed)?;
+        if key.is_some() {
+            self.keys += 1;
+        }
+        Ok(key)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Self::Error> {
+        // the rows have a key for every column, so the keys read so far give the column
+        self.inner.next_value_seed(seed).inspect_err(|_| *self.failed = self.keys.checked_sub(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "Mittauspisteen tunnus;Tuotteen tyyppi;Resoluutio;Yksikkötyyppi;Lukeman tyyppi;Alkuaika;Määrä;Laatu";
+
+    #[test]
+    fn reports_the_column_of_a_bad_value() {
+        // the bad amount equals the reading type, so it can not be found by its text
+        let data = format!(
+            "{}\n1;8716867000030;PT1H;kWh;BN01;2022-01-01T00:00:00Z;0,5;OK\n2;1;PT1H;kWh;BN01;2022-01-01T01:00:00Z;BN01;OK\n",
+            HEADER
+        );
+        let mut records = FingridParser::records_from_reader("test.csv", data.as_bytes(), true);
+        let parsed = records.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+        let report = records.into_report();
+
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(report.issues.len(), 1);
+        let issue = &report.issues[0];
+        assert_eq!(issue.line, Some(3));
+        assert_eq!(issue.column.as_deref(), Some("Määrä"));
+        assert_eq!(issue.raw_value, "BN01");
+    }
+
+    #[test]
+    fn reports_the_column_of_a_missing_cell() {
+        let data = format!("{}\n1;1;PT1H;kWh;BN01;2022-01-01T00:00:00Z\n", HEADER);
+        let mut records = FingridParser::records_from_reader("test.csv", data.as_bytes(), true);
+        assert_eq!(records.by_ref().count(), 0);
+        let report = records.into_report();
+
+        assert_eq!(report.issues.len(), 1);
+        assert_eq!(report.issues[0].column.as_deref(), Some("Määrä"));
     }
 }
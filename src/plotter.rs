@@ -1,61 +1,29 @@
 use std::error::Error;
-use std::ops::Range;
-use chrono::{Date, DateTime, TimeZone, Utc};
-use plotters::coord::ranged1d::{DefaultFormatting, KeyPointHint};
+use chrono::{DateTime, TimeZone, Utc};
 use plotters::prelude::*;
-use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
 
-#[derive(Debug)]
-struct WrappedUtc {
-    datetime: DateTime<Utc>
+/// Legend text for a fixed price in c/kWh, e.g. "6,99 c/kWh"
+pub fn contract_price_label(contract_price: Decimal) -> String {
+    format!("{} c/kWh", contract_price.normalize()).replace('.', ",")
 }
 
-impl Ranged for WrappedUtc {
-    type FormatOption = DefaultFormatting;
-    type ValueType = DateTime<Utc>;
-
-    fn map(&self, value: &Self::ValueType, limit: (i32, i32)) -> i32 {
-        todo!()
-    }
-
-    fn key_points<Hint: KeyPointHint>(&self, hint: Hint) -> Vec<Self::ValueType> {
-        todo!()
-    }
-
-    fn range(&self) -> Range<Self::ValueType> {
-        todo!()
-    }
-}
-impl DiscreteRanged for WrappedUtc {
-
-    fn size(&self) -> usize {
-        todo!()
-    }
-
-    fn index_of(&self, value: &DateTime<Utc>) -> Option<usize> {
-        todo!()
-    }
-
-    fn from_index(&self, index: usize) -> Option<DateTime<Utc>> {
-        todo!()
-    }
-}
+/// Points of one line in the chart
+pub type Series = Vec<(DateTime<Utc>, f64)>;
 
 pub fn draw_png(
     start: &DateTime<impl TimeZone>,
     end: &DateTime<impl TimeZone>,
     max_val: f64,
-    set_series: impl Iterator<Item = (DateTime<Utc>, f64)>,
+    contract_series: Vec<(String, Series)>,
     market_series: impl Iterator<Item = (DateTime<Utc>, f64)>,
+    highlighted: &[(DateTime<Utc>, DateTime<Utc>)],
 ) -> Result<(), Box<dyn Error>> {
-    let width = 2000.0;
-    let height = width / (16.0 / 9.0);
-    let height = f64::floor(height).to_i32().unwrap();
     let width = 1000;
     let height = 800;
     let root_area = BitMapBackend::new("test.png", (width, height)).into_drawing_area();
 
-    root_area.fill(&WHITE).unwrap();
+    root_area.fill(&WHITE)?;
 
     let title = format!("Hintavertailu {} - {}", start.date().naive_local(), end.date().naive_local());
 
@@ -86,10 +54,27 @@ pub fn draw_png(
         // .disable_y_mesh()
         .draw()?;
 
-    chart
-        .draw_series(LineSeries::new(set_series,RED))?
-        .label("6,99 €/kWh")
-        .legend(|(x, y)| Rectangle::new([(x - 15, y + 1), (x, y)], RED));
+    // periods of estimated consumption, drawn under the lines
+    if !highlighted.is_empty() {
+        let color = YELLOW.mix(0.3);
+        chart
+            .draw_series(
+                highlighted
+                    .iter()
+                    .map(|(from, to)| Rectangle::new([(*from, 0.0), (*to, max_val)], color.filled())),
+            )?
+            .label("Arvioitu kulutus")
+            .legend(move |(x, y)| Rectangle::new([(x - 15, y - 5), (x, y + 5)], color.filled()));
+    }
+
+    let contract_colors = [RED, GREEN, MAGENTA, CYAN, BLACK];
+    for (i, (label, series)) in contract_series.into_iter().enumerate() {
+        let color = contract_colors[i % contract_colors.len()];
+        chart
+            .draw_series(LineSeries::new(series, color))?
+            .label(label)
+            .legend(move |(x, y)| Rectangle::new([(x - 15, y + 1), (x, y)], color));
+    }
 
     chart
         .draw_series(LineSeries::new(market_series, BLUE))?
@@ -105,8 +90,7 @@ pub fn draw_png(
         .border_style(BLACK)
         .background_style(WHITE)
         .label_font(("Calibri", 20))
-        .draw()
-        .unwrap();
+        .draw()?;
 
 
     Ok(())
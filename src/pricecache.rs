@@ -0,0 +1,259 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io;
+use std::path::PathBuf;
+
+use chrono::{DateTime, Datelike, Duration, SecondsFormat, Utc};
+
+use crate::error::Error;
+use crate::priceclient::{start_of_hour, PriceData};
+
+/// Persistent price cache keyed by bidding zone and hour.
+///
+/// Prices are stored as one file per zone and month, `<dir>/<zone>/<yyyy>-<mm>.csv`, in the same
+/// `date;value` format that [`crate::pricefile::PriceFile`] reads. The hours that have been
+/// fetched are listed in `<yyyy>-<mm>.hours`, so that an hour is fetched once with all of its
+/// market time units, and an hour the remote has no price for is not fetched again.
+#[derive(Debug, Clone)]
+pub struct PriceCache {
+    dir: PathBuf,
+}
+
+impl PriceCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        PriceCache { dir: dir.into() }
+    }
+
+    /// Cached prices of `zone` between `start` and `end`, inclusive
+    pub(crate) fn get(
+        &self,
+        zone: &str,
+        start: &DateTime<Utc>,
+        end: &DateTime<Utc>,
+    ) -> Result<BTreeMap<DateTime<Utc>, PriceData>, Error> {
+        let mut prices = BTreeMap::new();
+        for month in months(start, end) {
+            prices.extend(
+                self.read_month(zone, month)?
+                    .into_iter()
+                    .filter(|(date, _)| date >= start && date <= end),
+            );
+        }
+        Ok(prices)
+    }
+
+    /// Merges `prices` into the cached months of `zone`
+    pub(crate) fn store(&self, zone: &str, prices: &[PriceData]) -> Result<(), Error> {
+        let mut by_month: BTreeMap<(i32, u32), Vec<PriceData>> = BTreeMap::new();
+        for price in prices {
+            by_month
+                .entry((price.date.year(), price.date.month()))
+                .or_default()
+                .push(*price);
+        }
+
+        for (month, prices) in by_month {
+            let mut cached = self.read_month(zone, month)?;
+            cached.extend(prices.into_iter().map(|p| (p.date, p)));
+
+            let path = self.month_path(zone, month);
+            self.create_zone_dir(zone)?;
+            let file = File::create(&path).map_err(|e| Error::io(&path, e))?;
+            let mut writer = csv::WriterBuilder::new().delimiter(b';').from_writer(file);
+            for price in cached.values() {
+                writer.serialize(price).map_err(|e| Error::csv(&path, e))?;
+            }
+            writer.flush().map_err(|e| Error::io(&path, e))?;
+        }
+        Ok(())
+    }
+
+    /// Starts of the hours of `zone` between `start` and `end` that have been fetched
+    pub(crate) fn fetched_hours(
+        &self,
+        zone: &str,
+        start: &DateTime<Utc>,
+        end: &DateTime<Utc>,
+    ) -> Result<BTreeSet<DateTime<Utc>>, Error> {
+        let first = start_of_hour(start);
+        let mut hours = BTreeSet::new();
+        for month in months(start, end) {
+            hours.extend(
+                self.read_hours(zone, month)?
+                    .into_iter()
+                    .filter(|hour| *hour >= first && hour <= end),
+            );
+        }
+        Ok(hours)
+    }
+
+    /// Records the hours between `start` and `end` as fetched. Hours that have not ended yet and
+    /// have no price in `prices` are left out, their prices may still be published.
+    pub(crate) fn mark_fetched(
+        &self,
+        zone: &str,
+        start: &DateTime<Utc>,
+        end: &DateTime<Utc>,
+        prices: &[PriceData],
+        now: &DateTime<Utc>,
+    ) -> Result<(), Error> {
+        let priced = prices.iter().map(|p| start_of_hour(&p.date)).collect::<BTreeSet<_>>();
+        let mut by_month: BTreeMap<(i32, u32), Vec<DateTime<Utc>>> = BTreeMap::new();
+        let mut hour = start_of_hour(start);
+        while hour <= *end {
+            if hour + Duration::hours(1) <= *now || priced.contains(&hour) {
+                by_month.entry((hour.year(), hour.month())).or_default().push(hour);
+            }
+            hour += Duration::hours(1);
+        }
+
+        for (month, hours) in by_month {
+            let mut fetched = self.read_hours(zone, month)?;
+            fetched.extend(hours);
+
+            let path = self.hours_path(zone, month);
+            self.create_zone_dir(zone)?;
+            let contents = fetched
+                .iter()
+                .map(|hour| hour.to_rfc3339_opts(SecondsFormat::Secs, true) + "\n")
+                .collect::<String>();
+            fs::write(&path, contents).map_err(|e| Error::io(&path, e))?;
+        }
+        Ok(())
+    }
+
+    /// Contiguous ranges of the hours between `start` and `end` that are not in `fetched`. Each
+    /// range runs to the end of its last hour, so that all quarter hour prices of an hour are
+    /// fetched together.
+    pub(crate) fn missing_ranges(
+        &self,
+        fetched: &BTreeSet<DateTime<Utc>>,
+        start: &DateTime<Utc>,
+        end: &DateTime<Utc>,
+    ) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+        let mut ranges: Vec<(DateTime<Utc>, DateTime<Utc>)> = Vec::new();
+        let mut hour = start_of_hour(start);
+        while hour <= *end {
+            if !fetched.contains(&hour) {
+                match ranges.last_mut() {
+                    Some((_, range_end)) if *range_end + Duration::hours(1) == hour => {
+                        *range_end = hour
+                    }
+                    _ => ranges.push((hour, hour)),
+                }
+            }
+            hour += Duration::hours(1);
+        }
+        ranges
+            .into_iter()
+            .map(|(first, last)| (first, last + Duration::hours(1) - Duration::milliseconds(1)))
+            .collect()
+    }
+
+    fn read_hours(&self, zone: &str, month: (i32, u32)) -> Result<BTreeSet<DateTime<Utc>>, Error> {
+        let path = self.hours_path(zone, month);
+        if !path.exists() {
+            return Ok(BTreeSet::new());
+        }
+        let contents = fs::read_to_string(&path).map_err(|e| Error::io(&path, e))?;
+        contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                DateTime::parse_from_rfc3339(line.trim())
+                    .map(|hour| hour.with_timezone(&Utc))
+                    .map_err(|e| Error::io(&path, io::Error::new(io::ErrorKind::InvalidData, e)))
+            })
+            .collect()
+    }
+
+    fn read_month(
+        &self,
+        zone: &str,
+        month: (i32, u32),
+    ) -> Result<BTreeMap<DateTime<Utc>, PriceData>, Error> {
+        let path = self.month_path(zone, month);
+        if !path.exists() {
+            return Ok(BTreeMap::new());
+        }
+        let file = File::open(&path).map_err(|e| Error::io(&path, e))?;
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(b';')
+            .has_headers(true)
+            .from_reader(file);
+        let mut prices = BTreeMap::new();
+        for price in reader.deserialize() {
+            let price: PriceData = price.map_err(|e| Error::csv(&path, e))?;
+            prices.insert(price.date, price);
+        }
+        Ok(prices)
+    }
+
+    fn create_zone_dir(&self, zone: &str) -> Result<(), Error> {
+        let dir = self.dir.join(zone);
+        fs::create_dir_all(&dir).map_err(|e| Error::io(&dir, e))
+    }
+
+    fn month_path(&self, zone: &str, (year, month): (i32, u32)) -> PathBuf {
+        self.dir.join(zone).join(format!("{year:04}-{month:02}.csv"))
+    }
+
+    fn hours_path(&self, zone: &str, (year, month): (i32, u32)) -> PathBuf {
+        self.dir.join(zone).join(format!("{year:04}-{month:02}.hours"))
+    }
+}
+
+/// Calendar months in UTC touched by the range `start..=end`
+fn months(start: &DateTime<Utc>, end: &DateTime<Utc>) -> Vec<(i32, u32)> {
+    let mut months = Vec::new();
+    let (mut year, mut month) = (start.year(), start.month());
+    while (year, month) <= (end.year(), end.month()) {
+        months.push((year, month));
+        (year, month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+    }
+    months
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn time(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.ymd(2025, 10, day).and_hms(hour, minute, 0)
+    }
+
+    #[test]
+    fn missing_ranges_run_to_the_end_of_the_hour() {
+        let cache = PriceCache::new("unused");
+        let fetched = BTreeSet::from([time(1, 1, 0)]);
+
+        let ranges = cache.missing_ranges(&fetched, &time(1, 0, 0), &time(1, 2, 0));
+
+        let end_of_hour = |hour| time(1, hour, 59) + Duration::seconds(59) + Duration::milliseconds(999);
+        assert_eq!(
+            ranges,
+            vec![(time(1, 0, 0), end_of_hour(0)), (time(1, 2, 0), end_of_hour(2))]
+        );
+    }
+
+    #[test]
+    fn hours_without_prices_are_fetched_once_they_have_ended() {
+        let dir = std::env::temp_dir().join(format!("eleparser-cache-{}", std::process::id()));
+        let cache = PriceCache::new(&dir);
+        let price = PriceData {
+            date: time(2, 0, 15),
+            value: 50.0,
+        };
+
+        // 00:00 has a quarter price, 01:00 has ended without one and 02:00 is still to come
+        cache
+            .mark_fetched("FI", &time(2, 0, 0), &time(2, 2, 0), &[price], &time(2, 2, 30))
+            .unwrap();
+        let fetched = cache.fetched_hours("FI", &time(2, 0, 0), &time(2, 2, 0)).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(fetched, BTreeSet::from([time(2, 0, 0), time(2, 1, 0)]));
+    }
+}
@@ -1,84 +1,395 @@
-use chrono::{DateTime, Duration, NaiveDateTime, SecondsFormat, Utc};
-use reqwest::Error;
+use std::collections::{BTreeMap, HashMap};
+use std::thread;
+use std::time::Duration as StdDuration;
+use chrono::{DateTime, Duration, SecondsFormat, Timelike, Utc};
+use chrono_intervals::{Grouping, IntervalGenerator};
+use reqwest::StatusCode;
 use rust_decimal::prelude::FromPrimitive;
 use rust_decimal::Decimal;
-use serde::Deserialize;
-use chrono_intervals::{Grouping, IntervalGenerator};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::pricecache::PriceCache;
+use crate::record::TimeResolution;
 
 #[derive(Deserialize, Debug)]
-struct ApiResponse {
-    prices: Vec<PriceData>
+pub(crate) struct ApiResponse {
+    pub(crate) prices: Vec<PriceData>
 }
 
-#[derive(Deserialize, Debug)]
-struct PriceData {
-    date: DateTime<Utc>,
-    value: f32,
+/// Spot price in €/MWh, as returned by sahkotin.fi
+#[derive(Deserialize, Serialize, Debug, Copy, Clone)]
+pub(crate) struct PriceData {
+    pub(crate) date: DateTime<Utc>,
+    pub(crate) value: f32,
+}
+
+/// Source of spot prices for a time range
+pub trait PriceSource {
+    fn get_prices(
+        &self,
+        start: &DateTime<Utc>,
+        end: &DateTime<Utc>,
+    ) -> Result<Vec<SpotPrice>, Error>;
+}
+
+impl<P> PriceSource for Box<P>
+where
+    P: PriceSource + ?Sized,
+{
+    fn get_prices(
+        &self,
+        start: &DateTime<Utc>,
+        end: &DateTime<Utc>,
+    ) -> Result<Vec<SpotPrice>, Error> {
+        self.as_ref().get_prices(start, end)
+    }
+}
+
+/// Length of the time ranges that a long price query is split into
+#[derive(Debug, Default, Copy, Clone)]
+pub enum QueryInterval {
+    Day,
+    Week,
+    #[default]
+    Month,
+}
+
+impl From<QueryInterval> for Grouping {
+    fn from(interval: QueryInterval) -> Self {
+        match interval {
+            QueryInterval::Day => Grouping::PerDay,
+            QueryInterval::Week => Grouping::PerWeek,
+            QueryInterval::Month => Grouping::PerMonth,
+        }
+    }
+}
+
+/// Called with the error and the delay before a failed query is retried
+pub type RetryHook = fn(&Error, StdDuration);
+
+/// Fetches prices from sahkotin.fi, optionally through an on-disk [`PriceCache`]
+#[derive(Debug, Default, Clone)]
+pub struct SahkotinClient {
+    cache: Option<PriceCache>,
+    query_interval: QueryInterval,
+    on_retry: Option<RetryHook>,
+}
+
+impl SahkotinClient {
+    pub fn with_cache(mut self, cache: PriceCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    pub fn with_query_interval(mut self, query_interval: QueryInterval) -> Self {
+        self.query_interval = query_interval;
+        self
+    }
+
+    /// Reports transient failures that are retried, e.g. to show them to the user
+    pub fn with_retry_hook(mut self, on_retry: RetryHook) -> Self {
+        self.on_retry = Some(on_retry);
+        self
+    }
+}
+
+impl PriceSource for SahkotinClient {
+    fn get_prices(
+        &self,
+        start: &DateTime<Utc>,
+        end: &DateTime<Utc>,
+    ) -> Result<Vec<SpotPrice>, Error> {
+        match &self.cache {
+            Some(cache) => get_cached_prices(cache, start, end, self.query_interval, self.on_retry),
+            None => {
+                let v = get_chunked_price_data(start, end, self.query_interval, self.on_retry)?;
+                spot_prices(&v)
+            }
+        }
+    }
 }
 
-/// Price for the following hour
-pub struct HourlyPrice {
+/// Reads prices from `cache` and fetches only the hours that are not cached yet
+fn get_cached_prices(
+    cache: &PriceCache,
+    start: &DateTime<Utc>,
+    end: &DateTime<Utc>,
+    query_interval: QueryInterval,
+    on_retry: Option<RetryHook>,
+) -> Result<Vec<SpotPrice>, Error> {
+    let mut prices = cache.get(BIDDING_ZONE, start, end)?;
+    let fetched_hours = cache.fetched_hours(BIDDING_ZONE, start, end)?;
+    for (missing_start, missing_end) in cache.missing_ranges(&fetched_hours, start, end) {
+        let fetched = get_chunked_price_data(&missing_start, &missing_end, query_interval, on_retry)?;
+        cache.store(BIDDING_ZONE, &fetched)?;
+        cache.mark_fetched(BIDDING_ZONE, &missing_start, &missing_end, &fetched, &Utc::now())?;
+        prices.extend(fetched.into_iter().map(|p| (p.date, p)));
+    }
+
+    let prices = prices
+        .range(*start..=*end)
+        .map(|(_, p)| *p)
+        .collect::<Vec<_>>();
+    spot_prices(&prices)
+}
+
+/// Price in c/kWh for the market time unit starting at `time`
+#[derive(Debug, Copy, Clone)]
+pub struct SpotPrice {
     pub time: DateTime<Utc>,
     pub price: Decimal,
+    pub resolution: TimeResolution,
 }
 
-impl From<&PriceData> for HourlyPrice {
-    fn from(p: &PriceData) -> Self {
-        // let time = NaiveDateTime::from_timestamp(p.date, 0);
-        let time = p.date;
-        let euros_per_kwh = Decimal::from_f32(p.value).unwrap() / Decimal::from(1000);
-        let cents_per_kwh = euros_per_kwh * Decimal::from(100);
-        HourlyPrice {
-            time,
+impl SpotPrice {
+    fn new(p: &PriceData, resolution: TimeResolution) -> Result<Self, Error> {
+        let euros_per_mwh = Decimal::from_f32(p.value).ok_or_else(|| Error::Decimal {
+            value: p.value.to_string(),
+        })?;
+        let cents_per_kwh = euros_per_mwh / Decimal::from(1000) * Decimal::from(100);
+        Ok(SpotPrice {
+            time: p.date,
             price: cents_per_kwh,
+            resolution,
+        })
+    }
+}
+
+/// Converts prices sorted by time. A price is for a quarter hour when it does not start on a whole
+/// hour, when its hour has other prices or when the previous price is a quarter hour before it,
+/// so that a missing quarter does not turn the quarters next to it into hourly prices.
+pub(crate) fn spot_prices(prices: &[PriceData]) -> Result<Vec<SpotPrice>, Error> {
+    let quarter = TimeResolution::PT15M.duration();
+    let mut per_hour: HashMap<DateTime<Utc>, usize> = HashMap::new();
+    for p in prices {
+        *per_hour.entry(start_of_hour(&p.date)).or_default() += 1;
+    }
+    prices
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            let previous = i.checked_sub(1).map(|i| p.date - prices[i].date);
+            let quarterly = p.date.minute() != 0
+                || per_hour.get(&start_of_hour(&p.date)).is_some_and(|count| *count > 1)
+                || previous == Some(quarter);
+            let resolution = if quarterly {
+                TimeResolution::PT15M
+            } else {
+                TimeResolution::PT1H
+            };
+            SpotPrice::new(p, resolution)
+        })
+        .collect()
+}
+
+pub(crate) fn start_of_hour(time: &DateTime<Utc>) -> DateTime<Utc> {
+    let into_hour = Duration::seconds(i64::from(time.minute() * 60 + time.second()))
+        + Duration::nanoseconds(i64::from(time.nanosecond()));
+    *time - into_hour
+}
+
+/// Spot prices indexed by the start of their market time unit
+pub(crate) struct PriceLookup {
+    hourly: HashMap<DateTime<Utc>, Decimal>,
+    quarterly: HashMap<DateTime<Utc>, Decimal>,
+}
+
+impl PriceLookup {
+    pub(crate) fn new(prices: &[SpotPrice]) -> Self {
+        let mut hourly = HashMap::new();
+        let mut quarterly = HashMap::new();
+        for p in prices {
+            match p.resolution {
+                TimeResolution::PT1H => hourly.insert(p.time, p.price),
+                TimeResolution::PT15M => quarterly.insert(p.time, p.price),
+            };
+        }
+        PriceLookup { hourly, quarterly }
+    }
+
+    /// Price for the interval of length `resolution` starting at `start`.
+    ///
+    /// Quarter hours use the 15 minute price when there is one and the price of the containing
+    /// hour otherwise. Hours without an hourly price use the average of their four quarters.
+    pub(crate) fn price(&self, start: &DateTime<Utc>, resolution: TimeResolution) -> Option<Decimal> {
+        match resolution {
+            TimeResolution::PT15M => self
+                .quarterly
+                .get(start)
+                .copied()
+                .or_else(|| self.hourly_price(&start_of_hour(start))),
+            TimeResolution::PT1H => self.hourly_price(start),
         }
     }
+
+    fn hourly_price(&self, hour: &DateTime<Utc>) -> Option<Decimal> {
+        self.hourly.get(hour).copied().or_else(|| {
+            let quarter = TimeResolution::PT15M.duration();
+            let quarters = (0..4)
+                .map(|q| self.quarterly.get(&(*hour + quarter * q)).copied())
+                .collect::<Option<Vec<_>>>()?;
+            Some(quarters.iter().sum::<Decimal>() / Decimal::from(4))
+        })
+    }
 }
 const URL: &str = "https://sahkotin.fi/prices";
+/// sahkotin.fi only serves the Finnish bidding zone
+const BIDDING_ZONE: &str = "FI";
+const MAX_ATTEMPTS: u32 = 4;
+const RETRY_DELAY: StdDuration = StdDuration::from_secs(1);
+
 pub fn get_prices(
     start: &DateTime<Utc>,
     end: &DateTime<Utc>,
-// ) -> Vec<HourlyPrice> {
-) -> Result<Vec<HourlyPrice>, reqwest::Error> {
-    let years = end.years_since(*start).unwrap();
+) -> Result<Vec<SpotPrice>, Error> {
+    get_chunked_prices(start, end, QueryInterval::default())
+}
 
-    let interval_gen = IntervalGenerator::new()
-        .with_grouping(Grouping::PerMonth)
-        .with_precision(Duration::days(1))
-        .without_extended_begin()
-        .without_extended_end();
+/// Fetches the prices in consecutive queries of at most `query_interval` each
+pub fn get_chunked_prices(
+    start: &DateTime<Utc>,
+    end: &DateTime<Utc>,
+    query_interval: QueryInterval,
+) -> Result<Vec<SpotPrice>, Error> {
+    let v = get_chunked_price_data(start, end, query_interval, None)?;
+    spot_prices(&v)
+}
 
-    // let intervals = interval_gen.get_intervals(*start, *end); todo split into multiple queries if timespan is greater than a year
-    let intervals = [(start, end)];
+/// Sorted and deduplicated prices from the queries covering `start..=end`
+fn get_chunked_price_data(
+    start: &DateTime<Utc>,
+    end: &DateTime<Utc>,
+    query_interval: QueryInterval,
+    on_retry: Option<RetryHook>,
+) -> Result<Vec<PriceData>, Error> {
+    // the first and last interval are extended to whole groups, so clamp them back to the range
+    let intervals = IntervalGenerator::new()
+        .with_grouping(query_interval.into())
+        .with_precision(Duration::milliseconds(1))
+        .get_intervals(*start, *end);
 
-    let result: Result<Vec<Vec<HourlyPrice>>, reqwest::Error> = intervals
-        .iter()
-        .map(|(interval_start, interval_end)| {
-            let prices = get_year_prices(interval_start, interval_end);
-            prices
-        }).collect();
+    let mut prices = BTreeMap::new();
+    for (interval_start, interval_end) in intervals {
+        let interval_start = interval_start.max(*start);
+        let interval_end = interval_end.min(*end);
+        for price in get_price_data_with_retry(&interval_start, &interval_end, on_retry)? {
+            prices.insert(price.date, price);
+        }
+    }
+    Ok(prices.into_values().collect())
+}
 
-    result.map(|v| {
-        v.into_iter().flatten().collect()
-    })
+fn get_price_data_with_retry(
+    start: &DateTime<Utc>,
+    end: &DateTime<Utc>,
+    on_retry: Option<RetryHook>,
+) -> Result<Vec<PriceData>, Error> {
+    let mut attempt = 1;
+    loop {
+        match get_price_data(start, end) {
+            Ok(prices) => return Ok(prices),
+            Err(e) if attempt < MAX_ATTEMPTS && is_transient(&e) => {
+                let delay = RETRY_DELAY * 2u32.pow(attempt - 1);
+                if let Some(on_retry) = on_retry {
+                    on_retry(&e, delay);
+                }
+                thread::sleep(delay);
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Whether a failed query may succeed when retried: the connection failed, or the server was
+/// overloaded or had an internal error. Invalid requests and responses are not retried.
+fn is_transient(error: &Error) -> bool {
+    match error {
+        Error::RemotePrice(e) => match e.status() {
+            Some(status) => status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS,
+            None => e.is_timeout() || e.is_connect() || e.is_request() || e.is_body(),
+        },
+        _ => false,
+    }
 }
 
 pub fn get_year_prices(
     start: &DateTime<Utc>,
     end: &DateTime<Utc>,
-) -> Result<Vec<HourlyPrice>, Error> {
+) -> Result<Vec<SpotPrice>, Error> {
+    let mut v = get_price_data(start, end)?;
+    v.sort_by_key(|p| p.date);
+    spot_prices(&v)
+}
+
+fn get_price_data(
+    start: &DateTime<Utc>,
+    end: &DateTime<Utc>,
+) -> Result<Vec<PriceData>, Error> {
     let start = start.to_rfc3339_opts(SecondsFormat::Millis, true);
     let end = end.to_rfc3339_opts(SecondsFormat::Millis, true);
-    println!("{}, {}", &start, &end);
     let client = reqwest::blocking::Client::new();
     let req = client.get(URL).query(&[("start", &start), ("end", &end)]);
+    let response = req.send()?.error_for_status()?.json::<ApiResponse>()?;
+    Ok(response.prices)
+}
 
-    println!("{:?}", req.try_clone().unwrap().build().unwrap());
-    // println!("{:?}", req);
-    let response = req.send()?.json::<ApiResponse>()?;
-    let v = response.prices
-        .iter()
-        .map(HourlyPrice::from)
-        .collect::<Vec<_>>();
-    Ok(v)
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn prices(minutes: &[i64]) -> Vec<PriceData> {
+        let start = Utc.ymd(2025, 10, 1).and_hms(0, 0, 0);
+        minutes
+            .iter()
+            .map(|m| PriceData {
+                date: start + Duration::minutes(*m),
+                value: 50.0,
+            })
+            .collect()
+    }
+
+    fn resolutions(minutes: &[i64]) -> Vec<TimeResolution> {
+        spot_prices(&prices(minutes))
+            .unwrap()
+            .iter()
+            .map(|p| p.resolution)
+            .collect()
+    }
+
+    #[test]
+    fn infers_hourly_prices() {
+        assert_eq!(resolutions(&[0, 60, 120]), vec![TimeResolution::PT1H; 3]);
+    }
+
+    #[test]
+    fn infers_quarter_prices_next_to_a_missing_quarter() {
+        assert_eq!(resolutions(&[0, 15, 45, 60, 75]), vec![TimeResolution::PT15M; 5]);
+        // the first quarter of an hour without its second one
+        assert_eq!(resolutions(&[0, 30, 45]), vec![TimeResolution::PT15M; 3]);
+        assert_eq!(resolutions(&[45, 60, 90]), vec![TimeResolution::PT15M; 3]);
+    }
+
+    #[test]
+    fn infers_a_change_from_hourly_to_quarter_prices() {
+        assert_eq!(
+            resolutions(&[0, 60, 120, 135]),
+            vec![TimeResolution::PT1H, TimeResolution::PT1H, TimeResolution::PT15M, TimeResolution::PT15M]
+        );
+    }
+
+    #[test]
+    fn quarter_hours_use_their_own_price() {
+        let prices = spot_prices(&prices(&[0, 15, 45])).unwrap();
+        let lookup = PriceLookup::new(&prices);
+        let start = Utc.ymd(2025, 10, 1).and_hms(0, 0, 0);
+
+        assert!(lookup.price(&start, TimeResolution::PT15M).is_some());
+        assert!(lookup.price(&(start + Duration::minutes(30)), TimeResolution::PT15M).is_none());
+        // an hour with a missing quarter has no price
+        assert!(lookup.price(&start, TimeResolution::PT1H).is_none());
+    }
 }
@@ -0,0 +1,75 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+
+use crate::error::Error;
+use crate::priceclient::{spot_prices, ApiResponse, PriceData, PriceSource, SpotPrice};
+
+/// Prices read from a local file instead of the network.
+///
+/// The file uses the same units as sahkotin.fi, €/MWh. Supported formats are
+/// * `.json`: the response body of `https://sahkotin.fi/prices`, i.e. `{"prices": [{"date": ..., "value": ...}]}`
+/// * `.csv`: semicolon separated with the header `date;value`
+///
+/// Timestamps can be hourly or 15 minutes apart.
+pub struct PriceFile {
+    prices: Vec<PriceData>,
+}
+
+impl PriceFile {
+    pub fn from_path(file_path: &Path) -> Result<Self, Error> {
+        let extension = file_path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+
+        let mut prices = match extension.as_deref() {
+            Some("json") => {
+                let file = File::open(file_path).map_err(|e| Error::io(file_path, e))?;
+                let response: ApiResponse = serde_json::from_reader(BufReader::new(file))
+                    .map_err(|source| Error::Json {
+                        path: file_path.to_path_buf(),
+                        source,
+                    })?;
+                response.prices
+            }
+            Some("csv") => {
+                let file = File::open(file_path).map_err(|e| Error::io(file_path, e))?;
+                let mut reader = csv::ReaderBuilder::new()
+                    .delimiter(b';')
+                    .has_headers(true)
+                    .from_reader(file);
+                reader
+                    .deserialize()
+                    .collect::<Result<Vec<PriceData>, _>>()
+                    .map_err(|e| Error::csv(file_path, e))?
+            }
+            _ => {
+                return Err(Error::UnsupportedFormat {
+                    path: file_path.to_path_buf(),
+                    expected: "a .csv or .json price file".to_string(),
+                })
+            }
+        };
+        prices.sort_by_key(|p| p.date);
+        Ok(PriceFile { prices })
+    }
+}
+
+impl PriceSource for PriceFile {
+    fn get_prices(
+        &self,
+        start: &DateTime<Utc>,
+        end: &DateTime<Utc>,
+    ) -> Result<Vec<SpotPrice>, Error> {
+        let prices = self
+            .prices
+            .iter()
+            .filter(|p| p.date >= *start && p.date <= *end)
+            .copied()
+            .collect::<Vec<_>>();
+        spot_prices(&prices)
+    }
+}
@@ -0,0 +1,553 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+use chrono_tz::Tz;
+use rust_decimal::Decimal;
+
+use crate::record::{ReadingQuality, Record, TimeResolution};
+
+/// Problem found in a series of readings. Times are interval boundaries in UTC.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QualityIssue {
+    /// No readings between `from` and `to`
+    Gap {
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        /// Number of intervals of the surrounding resolution that are missing
+        missing: i64,
+    },
+    /// Several readings end at `time`
+    Duplicate {
+        time: DateTime<Utc>,
+        count: usize,
+        /// Whether the readings have different energies
+        conflicting: bool,
+    },
+    /// The resolution changes at `time`
+    ResolutionChange {
+        time: DateTime<Utc>,
+        from: TimeResolution,
+        to: TimeResolution,
+    },
+    /// Consecutive readings of zero energy
+    ZeroRun {
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        intervals: usize,
+    },
+    /// Reading that is negative or far above the typical power of the series
+    Outlier { time: DateTime<Utc>, energy: Decimal },
+    /// Consecutive readings estimated by the network operator instead of measured
+    Estimated {
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        intervals: usize,
+    },
+}
+
+impl Display for QualityIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QualityIssue::Gap { from, to, missing } => {
+                write!(f, "{} - {}: {} missing intervals", from, to, missing)
+            }
+            QualityIssue::Duplicate {
+                time,
+                count,
+                conflicting,
+            } => write!(
+                f,
+                "{}: {} readings{}",
+                time,
+                count,
+                if *conflicting { " with different energies" } else { "" }
+            ),
+            QualityIssue::ResolutionChange { time, from, to } => {
+                write!(f, "{}: resolution changes from {:?} to {:?}", time, from, to)
+            }
+            QualityIssue::ZeroRun {
+                from,
+                to,
+                intervals,
+            } => write!(f, "{} - {}: {} readings of zero", from, to, intervals),
+            QualityIssue::Outlier { time, energy } => {
+                write!(f, "{}: unusual reading of {} kWh", time, energy)
+            }
+            QualityIssue::Estimated {
+                from,
+                to,
+                intervals,
+            } => write!(f, "{} - {}: {} estimated readings", from, to, intervals),
+        }
+    }
+}
+
+/// Result of [`QualityCheck::check`]
+#[derive(Debug, Default, Clone)]
+pub struct QualityReport {
+    /// Number of checked readings
+    pub readings: usize,
+    pub first: Option<DateTime<Utc>>,
+    pub last: Option<DateTime<Utc>>,
+    /// In time order
+    pub issues: Vec<QualityIssue>,
+}
+
+impl QualityReport {
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// Whether every interval of the checked range has exactly one reading
+    pub fn is_complete(&self) -> bool {
+        self.readings > 0
+            && !self.issues.iter().any(|i| {
+                matches!(
+                    i,
+                    QualityIssue::Gap { .. } | QualityIssue::Duplicate { .. }
+                )
+            })
+    }
+
+    /// Number of intervals without a reading
+    pub fn missing_intervals(&self) -> i64 {
+        self.issues
+            .iter()
+            .map(|i| match i {
+                QualityIssue::Gap { missing, .. } => *missing,
+                _ => 0,
+            })
+            .sum()
+    }
+
+    pub fn gaps(&self) -> usize {
+        self.count(|i| matches!(i, QualityIssue::Gap { .. }))
+    }
+
+    pub fn duplicates(&self) -> usize {
+        self.count(|i| matches!(i, QualityIssue::Duplicate { .. }))
+    }
+
+    pub fn resolution_changes(&self) -> usize {
+        self.count(|i| matches!(i, QualityIssue::ResolutionChange { .. }))
+    }
+
+    pub fn zero_runs(&self) -> usize {
+        self.count(|i| matches!(i, QualityIssue::ZeroRun { .. }))
+    }
+
+    pub fn outliers(&self) -> usize {
+        self.count(|i| matches!(i, QualityIssue::Outlier { .. }))
+    }
+
+    /// Number of runs of estimated readings
+    pub fn estimated_runs(&self) -> usize {
+        self.count(|i| matches!(i, QualityIssue::Estimated { .. }))
+    }
+
+    fn count(&self, f: impl Fn(&QualityIssue) -> bool) -> usize {
+        self.issues.iter().filter(|i| f(i)).count()
+    }
+}
+
+/// Checks that a series of readings of one channel is complete and plausible
+#[derive(Debug, Clone)]
+pub struct QualityCheck {
+    /// Shortest run of zero readings that is reported
+    pub min_zero_run: Duration,
+    /// A reading whose average power is this many times the median power is an outlier
+    pub outlier_factor: Decimal,
+    /// Time range the series should cover, otherwise only gaps between readings are found
+    pub range: Option<(DateTime<Utc>, DateTime<Utc>)>,
+}
+
+impl Default for QualityCheck {
+    fn default() -> Self {
+        QualityCheck {
+            min_zero_run: Duration::hours(6),
+            outlier_factor: Decimal::from(10),
+            range: None,
+        }
+    }
+}
+
+impl QualityCheck {
+    /// Also reports missing readings at the start and end of the given time range
+    pub fn within(mut self, start_time: &DateTime<Utc>, end_time: &DateTime<Utc>) -> Self {
+        self.range = Some((*start_time, *end_time));
+        self
+    }
+
+    /// Checks `records`, which can be in any order. Records of different metering points or
+    /// directions should be checked separately, see [`crate::channel::split_channels`].
+    pub fn check<'a, R>(&self, records: impl IntoIterator<Item = &'a R>) -> QualityReport
+    where
+        R: Record + 'a,
+    {
+        let mut readings = 0;
+        let mut by_time: BTreeMap<DateTime<Utc>, Vec<&R>> = BTreeMap::new();
+        for record in records {
+            readings += 1;
+            by_time.entry(record.date_time()).or_default().push(record);
+        }
+
+        let mut issues = Vec::new();
+        let mut previous: Option<&R> = None;
+        for (time, readings) in &by_time {
+            let record = readings[0];
+            if readings.len() > 1 {
+                issues.push(QualityIssue::Duplicate {
+                    time: *time,
+                    count: readings.len(),
+                    conflicting: readings.iter().any(|r| r.energy() != record.energy()),
+                });
+            }
+            match previous {
+                Some(previous) => {
+                    if previous.resolution() != record.resolution() {
+                        issues.push(QualityIssue::ResolutionChange {
+                            time: record.start_time(),
+                            from: previous.resolution(),
+                            to: record.resolution(),
+                        });
+                    }
+                    issues.extend(gap(previous.date_time(), record.start_time(), record.resolution()));
+                }
+                None => {
+                    if let Some((start, _)) = self.range {
+                        issues.extend(gap(start, record.start_time(), record.resolution()));
+                    }
+                }
+            }
+            previous = Some(record);
+        }
+        if let (Some(last), Some((_, end))) = (previous, self.range) {
+            issues.extend(gap(last.date_time(), end, last.resolution()));
+        }
+
+        let series = by_time.values().map(|r| r[0]).collect::<Vec<_>>();
+        issues.extend(self.zero_runs(&series));
+        issues.extend(self.outliers(&series));
+        issues.extend(
+            estimated_periods(series.iter().copied())
+                .into_iter()
+                .map(|(from, to, intervals)| QualityIssue::Estimated { from, to, intervals }),
+        );
+        issues.sort_by_key(|i| match i {
+            QualityIssue::Gap { from: time, .. }
+            | QualityIssue::Duplicate { time, .. }
+            | QualityIssue::ResolutionChange { time, .. }
+            | QualityIssue::ZeroRun { from: time, .. }
+            | QualityIssue::Outlier { time, .. }
+            | QualityIssue::Estimated { from: time, .. } => *time,
+        });
+
+        QualityReport {
+            readings,
+            first: series.first().map(|r| r.start_time()),
+            last: series.last().map(|r| r.date_time()),
+            issues,
+        }
+    }
+
+    /// Runs of zero readings without gaps between them
+    fn zero_runs<R>(&self, series: &[&R]) -> Vec<QualityIssue>
+    where
+        R: Record,
+    {
+        runs(series.iter().copied(), |r| r.energy().is_zero())
+            .into_iter()
+            .filter_map(|run| {
+                let (first, last) = (run.first()?, run.last()?);
+                (last.date_time() - first.start_time() >= self.min_zero_run).then(|| {
+                    QualityIssue::ZeroRun {
+                        from: first.start_time(),
+                        to: last.date_time(),
+                        intervals: run.len(),
+                    }
+                })
+            })
+            .collect()
+    }
+
+    /// Negative readings and readings whose average power is far above the median
+    fn outliers<R>(&self, series: &[&R]) -> Vec<QualityIssue>
+    where
+        R: Record,
+    {
+        let power = |r: &R| r.energy() * Decimal::from(60) / Decimal::from(r.resolution().duration().num_minutes());
+        let mut powers = series
+            .iter()
+            .map(|r| power(r))
+            .filter(|p| p.is_sign_positive() && !p.is_zero())
+            .collect::<Vec<_>>();
+        powers.sort();
+        let limit = powers.get(powers.len() / 2).map(|median| median * self.outlier_factor);
+
+        series
+            .iter()
+            .filter(|r| r.energy().is_sign_negative() || limit.is_some_and(|limit| power(r) > limit))
+            .map(|r| QualityIssue::Outlier {
+                time: r.date_time(),
+                energy: r.energy(),
+            })
+            .collect()
+    }
+}
+
+/// Gap between the end of one interval and the start of the next, if at least one interval of
+/// `resolution` fits in it
+fn gap(from: DateTime<Utc>, to: DateTime<Utc>, resolution: TimeResolution) -> Option<QualityIssue> {
+    let missing = (to - from).num_seconds() / resolution.duration().num_seconds();
+    (missing > 0).then_some(QualityIssue::Gap { from, to, missing })
+}
+
+/// Runs of consecutive records, in time order and without gaps between them, that match
+/// `predicate`
+fn runs<'a, R>(series: impl IntoIterator<Item = &'a R>, predicate: impl Fn(&R) -> bool) -> Vec<Vec<&'a R>>
+where
+    R: Record + 'a,
+{
+    let mut runs = Vec::new();
+    let mut run: Vec<&R> = Vec::new();
+    for record in series {
+        let continues = run.last().is_none_or(|last| last.date_time() == record.start_time());
+        if !predicate(record) || !continues {
+            runs.push(std::mem::take(&mut run));
+        }
+        if predicate(record) {
+            run.push(record);
+        }
+    }
+    runs.push(run);
+    runs.retain(|run| !run.is_empty());
+    runs
+}
+
+fn is_estimated<R>(record: &R) -> bool
+where
+    R: Record,
+{
+    record.quality() == Some(ReadingQuality::Estimated)
+}
+
+/// Start, end and number of intervals of each run of estimated readings in `records`, which must
+/// be in time order
+pub fn estimated_periods<'a, R>(records: impl IntoIterator<Item = &'a R>) -> Vec<(DateTime<Utc>, DateTime<Utc>, usize)>
+where
+    R: Record + 'a,
+{
+    runs(records, is_estimated)
+        .into_iter()
+        .filter_map(|run| Some((run.first()?.start_time(), run.last()?.date_time(), run.len())))
+        .collect()
+}
+
+/// Drops the readings estimated by the network operator
+pub fn without_estimated<R>(records: Vec<R>) -> Vec<R>
+where
+    R: Record,
+{
+    records.into_iter().filter(|r| !is_estimated(r)).collect()
+}
+
+/// What to do with readings estimated by the network operator
+#[derive(clap::ValueEnum, Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum EstimatedReadings {
+    /// Use them like measured readings
+    #[default]
+    Include,
+    /// Leave them out of the comparison
+    Exclude,
+    /// Use them and mark their periods in the chart
+    Highlight,
+}
+
+/// Energy of a day or month and how much of it was estimated, in kWh
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EstimatedShare {
+    /// The day, or the first day of the month
+    pub period: NaiveDate,
+    pub energy: Decimal,
+    pub estimated: Decimal,
+}
+
+impl EstimatedShare {
+    /// Share of the energy that was estimated, `None` without energy
+    pub fn share(&self) -> Option<Decimal> {
+        (!self.energy.is_zero()).then(|| self.estimated / self.energy)
+    }
+}
+
+/// Estimated energy of each local calendar day in `timezone`
+pub fn estimated_by_day<R>(records: &[R], timezone: Tz) -> Vec<EstimatedShare>
+where
+    R: Record,
+{
+    estimated_by(records, |r| r.start_time().with_timezone(&timezone).date_naive())
+}
+
+/// Estimated energy of each local calendar month in `timezone`
+pub fn estimated_by_month<R>(records: &[R], timezone: Tz) -> Vec<EstimatedShare>
+where
+    R: Record,
+{
+    estimated_by(records, |r| {
+        let date = r.start_time().with_timezone(&timezone).date_naive();
+        NaiveDate::from_ymd(date.year(), date.month(), 1)
+    })
+}
+
+fn estimated_by<R>(records: &[R], period: impl Fn(&R) -> NaiveDate) -> Vec<EstimatedShare>
+where
+    R: Record,
+{
+    let mut periods: BTreeMap<NaiveDate, EstimatedShare> = BTreeMap::new();
+    for record in records {
+        let period = period(record);
+        let share = periods.entry(period).or_insert(EstimatedShare {
+            period,
+            energy: Decimal::ZERO,
+            estimated: Decimal::ZERO,
+        });
+        share.energy += record.energy();
+        if is_estimated(record) {
+            share.estimated += record.energy();
+        }
+    }
+    periods.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use chrono_tz::Europe::Helsinki;
+
+    use super::*;
+    use crate::record::fingrid::FingridRecord;
+    use crate::record::Direction;
+
+    fn reading(date_time: DateTime<Utc>, energy: i64) -> FingridRecord {
+        FingridRecord {
+            metering_point: Some(1),
+            product_type: None,
+            resolution: TimeResolution::PT1H,
+            unit: None,
+            direction: Direction::Consumption,
+            date_time,
+            energy: Decimal::from(energy),
+            quality: Some(ReadingQuality::Measured),
+        }
+    }
+
+    /// Hourly readings of one energy ending at every hour after `start` until `end`
+    fn series(start: DateTime<Utc>, end: DateTime<Utc>, energy: i64) -> Vec<FingridRecord> {
+        let hours = (end - start).num_hours();
+        (1..=hours).map(|h| reading(start + Duration::hours(h), energy)).collect()
+    }
+
+    fn start() -> DateTime<Utc> {
+        Utc.ymd(2022, 1, 1).and_hms(0, 0, 0)
+    }
+
+    #[test]
+    fn complete_series_is_clean() {
+        let end = start() + Duration::days(1);
+        let report = QualityCheck::default().within(&start(), &end).check(&series(start(), end, 1));
+
+        assert!(report.is_clean());
+        assert!(report.is_complete());
+        assert_eq!(report.readings, 24);
+        assert_eq!(report.first, Some(start()));
+        assert_eq!(report.last, Some(end));
+    }
+
+    #[test]
+    fn finds_gaps_between_and_around_readings() {
+        let end = start() + Duration::days(1);
+        let mut records = series(start() + Duration::hours(2), end - Duration::hours(1), 1);
+        records.remove(5);
+        let report = QualityCheck::default().within(&start(), &end).check(&records);
+
+        assert_eq!(report.gaps(), 3);
+        assert_eq!(report.missing_intervals(), 4);
+        assert_eq!(
+            report.issues[0],
+            QualityIssue::Gap { from: start(), to: start() + Duration::hours(2), missing: 2 }
+        );
+        assert!(!report.is_complete());
+    }
+
+    #[test]
+    fn finds_duplicates() {
+        let end = start() + Duration::hours(4);
+        let mut records = series(start(), end, 1);
+        records.push(reading(start() + Duration::hours(1), 1));
+        records.push(reading(start() + Duration::hours(2), 5));
+        let report = QualityCheck::default().check(&records);
+
+        assert_eq!(report.readings, 6);
+        assert_eq!(
+            report.issues,
+            vec![
+                QualityIssue::Duplicate { time: start() + Duration::hours(1), count: 2, conflicting: false },
+                QualityIssue::Duplicate { time: start() + Duration::hours(2), count: 2, conflicting: true },
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_long_runs_of_zero() {
+        let mut records = series(start(), start() + Duration::hours(24), 1);
+        for record in records.iter_mut().skip(2).take(6) {
+            record.energy = Decimal::ZERO;
+        }
+        for record in records.iter_mut().skip(12).take(5) {
+            record.energy = Decimal::ZERO;
+        }
+        let report = QualityCheck::default().check(&records);
+
+        assert_eq!(
+            report.issues,
+            vec![QualityIssue::ZeroRun {
+                from: start() + Duration::hours(2),
+                to: start() + Duration::hours(8),
+                intervals: 6,
+            }]
+        );
+    }
+
+    #[test]
+    fn finds_outliers() {
+        let mut records = series(start(), start() + Duration::hours(24), 1);
+        records[3].energy = Decimal::from(11);
+        records[4].energy = Decimal::from(10);
+        records[5].energy = Decimal::from(-1);
+        let report = QualityCheck::default().check(&records);
+
+        assert_eq!(
+            report.issues,
+            vec![
+                QualityIssue::Outlier { time: start() + Duration::hours(4), energy: Decimal::from(11) },
+                QualityIssue::Outlier { time: start() + Duration::hours(6), energy: Decimal::from(-1) },
+            ]
+        );
+    }
+
+    #[test]
+    fn dst_days_are_complete() {
+        for (day, hours) in [(Helsinki.ymd(2022, 3, 27), 23), (Helsinki.ymd(2022, 10, 30), 25)] {
+            let start = day.and_hms(0, 0, 0).with_timezone(&Utc);
+            let end = day.succ().and_hms(0, 0, 0).with_timezone(&Utc);
+            let records = series(start, end, 1);
+            let report = QualityCheck::default().within(&start, &end).check(&records);
+
+            assert!(report.is_clean(), "{:?}: {:?}", day, report.issues);
+            assert_eq!(report.readings, hours);
+            let shares = estimated_by_day(&records, Helsinki);
+            assert_eq!(shares.len(), 1);
+            assert_eq!(shares[0].energy, Decimal::from(hours));
+        }
+    }
+}
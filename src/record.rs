@@ -1,24 +1,150 @@
 use std::fmt::{Display, Formatter};
-use std::str::FromStr;
 
-use chrono::{DateTime, TimeZone, Utc};
+use chrono::{DateTime, Duration, Utc};
 use rust_decimal::Decimal;
-use rust_decimal::prelude::FromPrimitive;
 use serde::{Deserialize, Serialize};
 
+pub mod decimal;
 pub mod fingrid;
 pub mod oomi;
 
-#[derive(Debug, Copy, Clone, Deserialize, Serialize)]
+#[derive(clap::ValueEnum, Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub enum TimeResolution {
+    #[value(name = "PT15M")]
     PT15M,
+    #[value(name = "PT1H")]
     PT1H,
 }
+
+impl TimeResolution {
+    pub fn duration(&self) -> Duration {
+        match self {
+            TimeResolution::PT15M => Duration::minutes(15),
+            TimeResolution::PT1H => Duration::hours(1),
+        }
+    }
+}
+
+/// Whether the energy was taken from or fed into the grid
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub enum Direction {
+    #[default]
+    Consumption,
+    Production,
+    /// The source has a reading type this parser does not know. These readings are never
+    /// selected as consumption or production.
+    Unknown,
+}
+
+impl Display for Direction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Direction::Consumption => write!(f, "consumption"),
+            Direction::Production => write!(f, "production"),
+            Direction::Unknown => write!(f, "unknown direction"),
+        }
+    }
+}
+
+/// Unit of the energy column in the source file
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum EnergyUnit {
+    #[serde(alias = "Wh")]
+    Wh,
+    #[serde(alias = "kWh")]
+    KWh,
+    #[serde(alias = "MWh")]
+    MWh,
+}
+
+impl EnergyUnit {
+    /// Converts `energy` in this unit to kWh
+    pub fn to_kwh(&self, energy: Decimal) -> Decimal {
+        match self {
+            EnergyUnit::Wh => energy / Decimal::from(1000),
+            EnergyUnit::KWh => energy,
+            EnergyUnit::MWh => energy * Decimal::from(1000),
+        }
+    }
+}
+
+/// How the reading was obtained
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+pub enum ReadingQuality {
+    Measured,
+    Estimated,
+    /// The source has a quality code this parser does not know
+    Unknown,
+}
+
 pub trait Record: Sized {
     fn resolution(&self) -> TimeResolution;
+    /// End of the metering interval
     fn date_time(&self) -> DateTime<Utc>;
+    /// Energy in kWh
     fn energy(&self) -> Decimal;
     fn temperature(&self) -> Option<f32>;
+
+    /// Metering point id (GSRN), when the source has one
+    fn metering_point(&self) -> Option<u64> {
+        None
+    }
+
+    fn direction(&self) -> Direction {
+        Direction::Consumption
+    }
+
+    /// Unit of the energy in the source file. [`Record::energy`] is always in kWh.
+    fn unit(&self) -> EnergyUnit {
+        EnergyUnit::KWh
+    }
+
+    /// Quality of the reading, when the source tells it
+    fn quality(&self) -> Option<ReadingQuality> {
+        None
+    }
+
+    /// Start of the metering interval
+    fn start_time(&self) -> DateTime<Utc> {
+        self.date_time() - self.resolution().duration()
+    }
+}
+
+impl<R> Record for &R
+where
+    R: Record,
+{
+    fn resolution(&self) -> TimeResolution {
+        (*self).resolution()
+    }
+
+    fn date_time(&self) -> DateTime<Utc> {
+        (*self).date_time()
+    }
+
+    fn energy(&self) -> Decimal {
+        (*self).energy()
+    }
+
+    fn temperature(&self) -> Option<f32> {
+        (*self).temperature()
+    }
+
+    fn metering_point(&self) -> Option<u64> {
+        (*self).metering_point()
+    }
+
+    fn direction(&self) -> Direction {
+        (*self).direction()
+    }
+
+    fn unit(&self) -> EnergyUnit {
+        (*self).unit()
+    }
+
+    fn quality(&self) -> Option<ReadingQuality> {
+        (*self).quality()
+    }
 }
 
 pub trait PricedRecord: Record + Sized {
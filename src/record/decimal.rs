@@ -0,0 +1,109 @@
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+
+use crate::Error;
+
+/// Characters used to group thousands, e.g. "12 345,6" or "12'345.6"
+const GROUP_SEPARATORS: [char; 4] = [' ', '\u{a0}', '\u{202f}', '\''];
+
+/// Parses a number as written in Finnish or English locale exactly into a `Decimal`.
+///
+/// Both `,` and `.` are accepted as the decimal separator with any number of decimals, and the
+/// value may have a sign and thousands separators. When both `,` and `.` are present the last one
+/// is the decimal separator, and a separator that appears more than once groups thousands. A
+/// single `,` or `.` is always a decimal separator, so "1,234" is 1.234, and a trailing one may
+/// have no decimals, so "5," is 5. Numbers that mix different thousands separators are rejected.
+pub fn parse_decimal(s: &str) -> Result<Decimal, Error> {
+    let invalid = || Error::Decimal {
+        value: s.to_string(),
+    };
+
+    let trimmed = s.trim();
+    let (negative, unsigned) = match trimmed.strip_prefix(['-', '\u{2212}']) {
+        Some(unsigned) => (true, unsigned),
+        None => (false, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+    };
+    let valid = |c: char| c.is_ascii_digit() || c == ',' || c == '.' || GROUP_SEPARATORS.contains(&c);
+    if !unsigned.contains(|c: char| c.is_ascii_digit()) || !unsigned.chars().all(valid) {
+        return Err(invalid());
+    }
+
+    let decimal_separator = match (unsigned.rfind(','), unsigned.rfind('.')) {
+        (Some(comma), Some(dot)) => Some(if comma > dot { ',' } else { '.' }),
+        (Some(_), None) if unsigned.matches(',').count() == 1 => Some(','),
+        (None, Some(_)) if unsigned.matches('.').count() == 1 => Some('.'),
+        _ => None,
+    };
+    let (whole, fraction) = match decimal_separator {
+        Some(separator) => unsigned.rsplit_once(separator).ok_or_else(invalid)?,
+        None => (unsigned, ""),
+    };
+
+    // every group after the first must have exactly three digits and the same separator
+    let mut separators = whole.matches(|c: char| !c.is_ascii_digit());
+    let mixed = separators.next().is_some_and(|first| separators.any(|s| s != first));
+    let mut groups = whole.split(|c: char| !c.is_ascii_digit());
+    let first = groups.next().unwrap_or_default();
+    if mixed
+        || (first.len() > 3 && whole.len() > first.len())
+        || groups.any(|g| g.len() != 3)
+        || !fraction.chars().all(|c| c.is_ascii_digit())
+    {
+        return Err(invalid());
+    }
+
+    let whole = whole.replace(|c: char| !c.is_ascii_digit(), "");
+    let whole = if whole.is_empty() { "0" } else { &whole };
+    let normalized = if fraction.is_empty() {
+        whole.to_string()
+    } else {
+        format!("{}.{}", whole, fraction)
+    };
+    let value = Decimal::from_str_exact(&normalized)
+        .or_else(|_| Decimal::from_str(&normalized))
+        .map_err(|_| invalid())?;
+    Ok(if negative { -value } else { value })
+}
+
+/// Deserializes a cell with [`parse_decimal`]
+pub(crate) fn deserialize_decimal<'de, D>(deserializer: D) -> Result<Decimal, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: &str = Deserialize::deserialize(deserializer)?;
+    parse_decimal(s).map_err(D::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn parses_finnish_and_english_numbers() {
+        assert_eq!(parse_decimal("1,5").unwrap(), dec!(1.5));
+        assert_eq!(parse_decimal("1.234,56").unwrap(), dec!(1234.56));
+        assert_eq!(parse_decimal("1,234.56").unwrap(), dec!(1234.56));
+        assert_eq!(parse_decimal("-0,5").unwrap(), dec!(-0.5));
+        assert_eq!(parse_decimal("1 234,5").unwrap(), dec!(1234.5));
+        assert_eq!(parse_decimal("1,234").unwrap(), dec!(1.234));
+        assert_eq!(parse_decimal(" 0,000 ").unwrap(), dec!(0));
+    }
+
+    #[test]
+    fn accepts_a_trailing_decimal_separator() {
+        assert_eq!(parse_decimal("5,").unwrap(), dec!(5));
+        assert_eq!(parse_decimal("5.").unwrap(), dec!(5));
+        assert_eq!(parse_decimal("1.234,").unwrap(), dec!(1234));
+    }
+
+    #[test]
+    fn rejects_ambiguous_numbers() {
+        for value in ["", ",", "-", "1,2,3", "1.2.3", "1,5.3", "1,234,", "12 34,5", "1 234.567,8", "1,2e3", "NaN"] {
+            assert!(parse_decimal(value).is_err(), "{:?} should be rejected", value);
+        }
+    }
+}
@@ -1,49 +1,68 @@
 use chrono::{DateTime, Utc};
 use rust_decimal::Decimal;
-use crate::record::{Record, TimeResolution};
+use crate::record::decimal::deserialize_decimal;
+use crate::record::{Direction, EnergyUnit, ReadingQuality, Record, TimeResolution};
 
 use serde::{Deserialize, Deserializer, Serialize};
-use serde::de::Error;
 
 
+/// A reading of a Datahub export. Older exports only have the resolution, start time and amount,
+/// so the other columns are optional.
 #[derive(Debug, Copy, Clone, Deserialize, Serialize)]
 pub struct FingridRecord {
-    // #[serde(alias="Mittauspisteen tunnus")]
-    // pub id: i32,
-    //
-    // #[serde(alias="Tuotteen tyyppi")]
-    // pub product_type: i32,
-    //
+    #[serde(alias="Mittauspisteen tunnus", default)]
+    pub metering_point: Option<u64>,
+
+    #[serde(alias="Tuotteen tyyppi", default)]
+    pub product_type: Option<u64>,
+
     #[serde(alias="Resoluutio")]
     pub resolution: TimeResolution,
-    //
-    // #[serde(alias="Yksikkötyyppi")]
-    // pub unit_type: &'static str,
-    //
-    // #[serde(alias="Lukeman tyyppi")]
-    // pub reading_type: &'static str,
+
+    /// kWh when the export does not tell
+    #[serde(alias="Yksikkötyyppi", default)]
+    pub unit: Option<EnergyUnit>,
+
+    /// Datahub reading type BN01 is consumption, which is also assumed when the column is missing
+    /// or empty, and BN02 is production. Other reading types are kept as [`Direction::Unknown`].
+    #[serde(alias="Lukeman tyyppi", deserialize_with = "from_reading_type", default)]
+    pub direction: Direction,
 
     #[serde(alias="Alkuaika")]
     pub date_time: DateTime<Utc>,
 
-    #[serde(alias="Määrä", deserialize_with = "from_decimal_comma")]
-    pub energy: Decimal
+    /// Energy in `unit`
+    #[serde(alias="Määrä", deserialize_with = "deserialize_decimal")]
+    pub energy: Decimal,
+
+    #[serde(alias="Laatu", deserialize_with = "from_quality", default)]
+    pub quality: Option<ReadingQuality>,
 }
 
-fn from_decimal_comma<'de, D>(deserializer: D) -> Result<Decimal, D::Error>
-    where
-        D: Deserializer<'de>,
+fn from_reading_type<'de, D>(deserializer: D) -> Result<Direction, D::Error>
+where
+    D: Deserializer<'de>,
 {
     let s: &str = Deserialize::deserialize(deserializer)?;
-    let mut split = s.split(",");
-    let whole = split.next().unwrap().parse::<i16>().unwrap();
-    let scale = 100000;
-    let frac = split.next().unwrap().parse::<i64>().unwrap() * scale;
-
-    let whole_d = Decimal::from(whole);
-    let frac_d = Decimal::new(frac, 11);
+    match s.trim() {
+        "BN01" | "" => Ok(Direction::Consumption),
+        "BN02" => Ok(Direction::Production),
+        _ => Ok(Direction::Unknown),
+    }
+}
 
-    Ok(whole_d + frac_d)
+fn from_quality<'de, D>(deserializer: D) -> Result<Option<ReadingQuality>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: &str = Deserialize::deserialize(deserializer)?;
+    let quality = match s.trim().to_ascii_uppercase().as_str() {
+        "" => None,
+        "OK" | "MEASURED" | "REVISED" => Some(ReadingQuality::Measured),
+        "E" | "ESTIMATED" => Some(ReadingQuality::Estimated),
+        _ => Some(ReadingQuality::Unknown),
+    };
+    Ok(quality)
 }
 
 impl Record for FingridRecord {
@@ -56,11 +75,74 @@ impl Record for FingridRecord {
     }
 
     fn energy(&self) -> Decimal {
-        self.energy
+        self.unit().to_kwh(self.energy)
     }
 
     fn temperature(&self) -> Option<f32> {
         None
     }
 
+    fn metering_point(&self) -> Option<u64> {
+        self.metering_point
+    }
+
+    fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    fn unit(&self) -> EnergyUnit {
+        self.unit.unwrap_or(EnergyUnit::KWh)
+    }
+
+    fn quality(&self) -> Option<ReadingQuality> {
+        self.quality
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{EnergyParser, FingridParser};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn parses_an_export_without_the_optional_columns() {
+        let data = "Resoluutio;Alkuaika;Määrä\nPT1H;2022-01-01T00:00:00Z;0,5\n";
+        let records = FingridParser::parse_bytes("old.csv", data.as_bytes()).unwrap();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].metering_point(), None);
+        assert_eq!(records[0].direction(), Direction::Consumption);
+        assert_eq!(records[0].unit(), EnergyUnit::KWh);
+        assert_eq!(records[0].quality(), None);
+        assert_eq!(records[0].energy(), dec!(0.5));
+    }
+
+    #[test]
+    fn keeps_unknown_reading_types() {
+        let data = "Mittauspisteen tunnus;Tuotteen tyyppi;Resoluutio;Yksikkötyyppi;Lukeman tyyppi;Alkuaika;Määrä;Laatu\n\
+            1;8716867000030;PT1H;Wh;BN01;2022-01-01T00:00:00Z;500;OK\n\
+            1;8716867000030;PT1H;Wh;XX99;2022-01-01T01:00:00Z;500;OK\n";
+        let records = FingridParser::parse_bytes("new.csv", data.as_bytes()).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].metering_point(), Some(1));
+        assert_eq!(records[0].energy(), dec!(0.5));
+        assert_eq!(records[0].quality(), Some(ReadingQuality::Measured));
+        assert_eq!(records[0].direction(), Direction::Consumption);
+        assert_eq!(records[1].direction(), Direction::Unknown);
+    }
+
+    #[test]
+    fn parses_production_readings() {
+        let data = "Mittauspisteen tunnus;Tuotteen tyyppi;Resoluutio;Yksikkötyyppi;Lukeman tyyppi;Alkuaika;Määrä;Laatu\n\
+            2;8716867000030;PT15M;kWh;BN02;2022-06-01T09:00:00Z;0,25;OK\n";
+        let records = FingridParser::parse_bytes("production.csv", data.as_bytes()).unwrap();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].metering_point(), Some(2));
+        assert_eq!(records[0].direction(), Direction::Production);
+        assert_eq!(records[0].energy(), dec!(0.25));
+    }
 }
@@ -1,8 +1,7 @@
-use std::num::ParseFloatError;
-use crate::record::{Record, TimeResolution};
-use chrono::{Datelike, DateTime, LocalResult, NaiveDateTime, Timelike, TimeZone, Utc};
+use crate::record::decimal::deserialize_decimal;
+use crate::record::{ReadingQuality, Record, TimeResolution};
+use chrono::{Datelike, DateTime, Duration, LocalResult, NaiveDateTime, TimeZone, Utc};
 use chrono_tz::Europe::Helsinki;
-use chrono_tz::Tz;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Deserializer};
 use serde::de::Error;
@@ -20,7 +19,7 @@ pub struct OomiRecord {
 
     #[serde(
         alias = "Energia yhteensä (kWh)",
-        deserialize_with = "oomi_from_decimal_comma"
+        deserialize_with = "deserialize_decimal"
     )]
     pub energy: Decimal,
 
@@ -30,6 +29,8 @@ pub struct OomiRecord {
     #[serde(alias = "Lämpötila (°C)", deserialize_with = "from_f32_comma", default)]
     pub temperature: Option<f32>,
 
+    /// Oomi exports do not have a resolution column, the readings are always hourly
+    #[serde(default = "oomi_resolution")]
     pub resolution: TimeResolution,
 }
 
@@ -45,31 +46,32 @@ impl Default for OomiRecord {
     }
 }
 
+fn oomi_resolution() -> TimeResolution {
+    TimeResolution::PT1H
+}
+
 fn from_oomi_date<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
 where
     D: Deserializer<'de>,
 {
     let s: &str = Deserialize::deserialize(deserializer)?;
     let date = NaiveDateTime::parse_from_str(s,"%d.%m.%Y %H:%M")
-        .map_err(|e| D::Error::custom(format!("Unable to parse date: '{}'", s)))?;
+        .map_err(|_| D::Error::custom(format!("Unable to parse date: '{}'", s)))?;
 
     let helsinki_time = match Helsinki.from_local_datetime(&date) {
         LocalResult::None => Err(D::Error::custom(format!("Unable to parse date: '{}'", s))),
         LocalResult::Single(a) => Ok(a),
-        LocalResult::Ambiguous(a, b) => {
+        LocalResult::Ambiguous(_, _) => {
             // try to to recover daylight saving time
-
-            let dst_fuck = if date.month() > 6 {
-                eprintln!("Ambiguous date time: {}. Ranges from {} to {}. Assume as normal time", s, a, b);
-                let offset = date.with_hour(date.hour()+1).unwrap();
-                Helsinki.from_local_datetime(&offset).unwrap()
+            let shifted = if date.month() > 6 {
+                date + Duration::hours(1)
             } else {
-                eprintln!("Ambiguous date time: {}. Ranges from {} to {}. Assume as daylight saving time", s, a, b);
-                let offset = date.with_hour(date.hour()-1).unwrap();
-                Helsinki.from_local_datetime(&offset).unwrap()
+                date - Duration::hours(1)
             };
-            Ok(dst_fuck)
-            // Err(D::Error::custom(format!("Ambigious date time: {}. Ranges from {} to {}", s, a, b)))
+            Helsinki
+                .from_local_datetime(&shifted)
+                .single()
+                .ok_or_else(|| D::Error::custom(format!("Ambiguous date time: '{}'", s)))
         }
     }?;
 
@@ -92,22 +94,6 @@ where
 
 }
 
-fn oomi_from_decimal_comma<'de, D>(deserializer: D) -> Result<Decimal, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    let s: &str = Deserialize::deserialize(deserializer)?;
-    let mut split = s.split(",");
-    let whole = split.next().unwrap().parse::<i16>().unwrap();
-    let scale = 100;
-    let frac = split.next().unwrap().parse::<i64>().unwrap() * scale;
-
-    let whole_d = Decimal::from(whole);
-    let frac_d = Decimal::new(frac, 2);
-
-    Ok(whole_d + frac_d)
-}
-
 impl Record for OomiRecord {
     fn resolution(&self) -> TimeResolution {
         self.resolution
@@ -124,4 +110,11 @@ impl Record for OomiRecord {
     fn temperature(&self) -> Option<f32> {
         self.temperature
     }
+
+    fn quality(&self) -> Option<ReadingQuality> {
+        self.status.map(|status| match status {
+            OomiStatus::Mitattu => ReadingQuality::Measured,
+            OomiStatus::Laskettu => ReadingQuality::Estimated,
+        })
+    }
 }
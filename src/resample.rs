@@ -0,0 +1,301 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use rust_decimal::Decimal;
+
+use crate::channel::Channel;
+use crate::error::Error;
+use crate::record::{Direction, ReadingQuality, Record, TimeResolution};
+
+/// Reading converted to another resolution by [`resample`]
+#[derive(Debug, Copy, Clone)]
+pub struct ResampledRecord {
+    pub date_time: DateTime<Utc>,
+    pub resolution: TimeResolution,
+    /// Energy in kWh
+    pub energy: Decimal,
+    pub metering_point: Option<u64>,
+    pub direction: Direction,
+    /// Estimated when any of the original readings was
+    pub quality: Option<ReadingQuality>,
+    pub temperature: Option<f32>,
+}
+
+impl ResampledRecord {
+    fn from_record<R>(record: &R) -> Self
+    where
+        R: Record,
+    {
+        ResampledRecord {
+            date_time: record.date_time(),
+            resolution: record.resolution(),
+            energy: record.energy(),
+            metering_point: record.metering_point(),
+            direction: record.direction(),
+            quality: record.quality(),
+            temperature: record.temperature(),
+        }
+    }
+}
+
+impl Record for ResampledRecord {
+    fn resolution(&self) -> TimeResolution {
+        self.resolution
+    }
+
+    fn date_time(&self) -> DateTime<Utc> {
+        self.date_time
+    }
+
+    fn energy(&self) -> Decimal {
+        self.energy
+    }
+
+    fn temperature(&self) -> Option<f32> {
+        self.temperature
+    }
+
+    fn metering_point(&self) -> Option<u64> {
+        self.metering_point
+    }
+
+    fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    fn quality(&self) -> Option<ReadingQuality> {
+        self.quality
+    }
+}
+
+/// What to do with an hour that does not have all four quarter hour readings
+#[derive(clap::ValueEnum, Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum IncompleteHours {
+    /// Leave the hour out
+    #[default]
+    Drop,
+    /// Sum the readings the hour has
+    Keep,
+    /// Fail
+    Error,
+}
+
+/// Converts `records` to `resolution`. Quarter hours are summed into hours, checking with
+/// `incomplete` that each hour has all of its quarters, and hours are split evenly into quarters.
+/// Records already in `resolution` are kept as they are, and a quarter hour reading is preferred
+/// over a quarter split from an overlapping hourly reading. The result is ordered by channel and
+/// time.
+pub fn resample<R>(
+    records: impl IntoIterator<Item = R>,
+    resolution: TimeResolution,
+    incomplete: IncompleteHours,
+) -> Result<Vec<ResampledRecord>, Error>
+where
+    R: Record,
+{
+    match resolution {
+        TimeResolution::PT1H => to_hours(records, incomplete),
+        TimeResolution::PT15M => Ok(to_quarters(records)),
+    }
+}
+
+fn to_hours<R>(
+    records: impl IntoIterator<Item = R>,
+    incomplete: IncompleteHours,
+) -> Result<Vec<ResampledRecord>, Error>
+where
+    R: Record,
+{
+    let hour = TimeResolution::PT1H.duration();
+    let mut hours: BTreeMap<(Channel, DateTime<Utc>), Vec<R>> = BTreeMap::new();
+    for record in records {
+        let start = record
+            .start_time()
+            .duration_trunc(hour)
+            .expect("an hour fits in a timestamp");
+        hours.entry((Channel::of(&record), start)).or_default().push(record);
+    }
+
+    let mut resampled = Vec::with_capacity(hours.len());
+    for ((channel, start), readings) in hours {
+        let covered = readings
+            .iter()
+            .map(|r| r.resolution().duration())
+            .fold(Duration::zero(), |sum, d| sum + d);
+        let distinct = readings.iter().map(|r| r.date_time()).collect::<BTreeSet<_>>();
+        let complete = covered == hour && distinct.len() == readings.len();
+        if !complete {
+            match incomplete {
+                IncompleteHours::Drop => continue,
+                IncompleteHours::Keep => {}
+                IncompleteHours::Error => {
+                    return Err(Error::IncompleteInterval {
+                        time: start + hour,
+                        metering_point: channel.metering_point,
+                    })
+                }
+            }
+        }
+
+        let temperatures = readings.iter().filter_map(|r| r.temperature()).collect::<Vec<_>>();
+        resampled.push(ResampledRecord {
+            date_time: start + hour,
+            resolution: TimeResolution::PT1H,
+            energy: readings.iter().map(|r| r.energy()).sum(),
+            metering_point: channel.metering_point,
+            direction: channel.direction,
+            quality: combined_quality(&readings),
+            temperature: (!temperatures.is_empty())
+                .then(|| temperatures.iter().sum::<f32>() / temperatures.len() as f32),
+        });
+    }
+    Ok(resampled)
+}
+
+fn to_quarters<R>(records: impl IntoIterator<Item = R>) -> Vec<ResampledRecord>
+where
+    R: Record,
+{
+    let mut quarters: BTreeMap<(Channel, DateTime<Utc>), ResampledRecord> = BTreeMap::new();
+    for record in records {
+        let record = ResampledRecord::from_record(&record);
+        match record.resolution {
+            // replaces a quarter split from an hour read before
+            TimeResolution::PT15M => {
+                quarters.insert((Channel::of(&record), record.date_time), record);
+            }
+            TimeResolution::PT1H => {
+                let quarter = TimeResolution::PT15M.duration();
+                for i in 0..4 {
+                    let split = ResampledRecord {
+                        date_time: record.start_time() + quarter * (i + 1),
+                        resolution: TimeResolution::PT15M,
+                        energy: record.energy / Decimal::from(4),
+                        ..record
+                    };
+                    // keeps a quarter hour reading read before
+                    quarters.entry((Channel::of(&split), split.date_time)).or_insert(split);
+                }
+            }
+        }
+    }
+    quarters.into_values().collect()
+}
+
+/// Estimated if any reading is, measured if all are and otherwise unknown
+fn combined_quality<R>(readings: &[R]) -> Option<ReadingQuality>
+where
+    R: Record,
+{
+    let qualities = readings.iter().map(|r| r.quality()).collect::<Vec<_>>();
+    if qualities.contains(&Some(ReadingQuality::Estimated)) {
+        Some(ReadingQuality::Estimated)
+    } else if qualities.iter().all(|q| *q == Some(ReadingQuality::Measured)) {
+        Some(ReadingQuality::Measured)
+    } else if qualities.iter().all(|q| q.is_none()) {
+        None
+    } else {
+        Some(ReadingQuality::Unknown)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use chrono_tz::Europe::Helsinki;
+
+    use super::*;
+    use crate::record::fingrid::FingridRecord;
+
+    fn reading(date_time: DateTime<Utc>, resolution: TimeResolution, energy: i64) -> FingridRecord {
+        FingridRecord {
+            metering_point: Some(1),
+            product_type: None,
+            resolution,
+            unit: None,
+            direction: Direction::Consumption,
+            date_time,
+            energy: Decimal::from(energy),
+            quality: Some(ReadingQuality::Measured),
+        }
+    }
+
+    /// Readings of `resolution` covering `start..end`
+    fn series(start: DateTime<Utc>, end: DateTime<Utc>, resolution: TimeResolution) -> Vec<FingridRecord> {
+        let count = (end - start).num_minutes() / resolution.duration().num_minutes();
+        (1..=count as i32)
+            .map(|i| reading(start + resolution.duration() * i, resolution, 1))
+            .collect()
+    }
+
+    fn start() -> DateTime<Utc> {
+        Utc.ymd(2022, 1, 1).and_hms(0, 0, 0)
+    }
+
+    #[test]
+    fn sums_quarters_into_hours_on_dst_days() {
+        for (day, hours) in [(Helsinki.ymd(2022, 3, 27), 23), (Helsinki.ymd(2022, 10, 30), 25)] {
+            let start = day.and_hms(0, 0, 0).with_timezone(&Utc);
+            let end = day.succ().and_hms(0, 0, 0).with_timezone(&Utc);
+            let quarters = series(start, end, TimeResolution::PT15M);
+            let resampled = resample(quarters, TimeResolution::PT1H, IncompleteHours::Error).unwrap();
+
+            assert_eq!(resampled.len(), hours);
+            assert!(resampled.iter().all(|r| r.energy == Decimal::from(4)));
+            assert_eq!(resampled.last().map(|r| r.date_time), Some(end));
+        }
+    }
+
+    #[test]
+    fn splits_hours_into_quarters_on_dst_days() {
+        for (day, hours) in [(Helsinki.ymd(2022, 3, 27), 23), (Helsinki.ymd(2022, 10, 30), 25)] {
+            let start = day.and_hms(0, 0, 0).with_timezone(&Utc);
+            let end = day.succ().and_hms(0, 0, 0).with_timezone(&Utc);
+            let hourly = series(start, end, TimeResolution::PT1H);
+            let resampled = resample(hourly, TimeResolution::PT15M, IncompleteHours::Error).unwrap();
+
+            assert_eq!(resampled.len(), hours * 4);
+            assert!(resampled.iter().all(|r| r.energy == Decimal::new(25, 2)));
+        }
+    }
+
+    #[test]
+    fn handles_incomplete_hours() {
+        let mut quarters = series(start(), start() + Duration::hours(2), TimeResolution::PT15M);
+        quarters.remove(1);
+
+        let dropped = resample(quarters.clone(), TimeResolution::PT1H, IncompleteHours::Drop).unwrap();
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].date_time, start() + Duration::hours(2));
+
+        let kept = resample(quarters.clone(), TimeResolution::PT1H, IncompleteHours::Keep).unwrap();
+        assert_eq!(kept.iter().map(|r| r.energy).collect::<Vec<_>>(), vec![Decimal::from(3), Decimal::from(4)]);
+
+        let failed = resample(quarters, TimeResolution::PT1H, IncompleteHours::Error);
+        assert!(matches!(
+            failed,
+            Err(Error::IncompleteInterval { time, metering_point: Some(1) }) if time == start() + Duration::hours(1)
+        ));
+    }
+
+    #[test]
+    fn duplicate_quarter_makes_an_hour_incomplete() {
+        let mut quarters = series(start(), start() + Duration::hours(1), TimeResolution::PT15M);
+        quarters[3] = quarters[2];
+
+        let resampled = resample(quarters, TimeResolution::PT1H, IncompleteHours::Drop).unwrap();
+        assert!(resampled.is_empty());
+    }
+
+    #[test]
+    fn prefers_quarter_readings_over_split_hours() {
+        let hour = reading(start() + Duration::hours(1), TimeResolution::PT1H, 4);
+        let quarter = reading(start() + Duration::minutes(30), TimeResolution::PT15M, 3);
+
+        for records in [vec![hour, quarter], vec![quarter, hour]] {
+            let resampled = resample(records, TimeResolution::PT15M, IncompleteHours::Error).unwrap();
+            let energies = resampled.iter().map(|r| r.energy).collect::<Vec<_>>();
+            assert_eq!(energies, vec![Decimal::from(1), Decimal::from(3), Decimal::from(1), Decimal::from(1)]);
+        }
+    }
+}
@@ -0,0 +1,435 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use chrono_tz::Europe::Helsinki;
+use chrono_tz::Tz;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+use crate::bins::Bins;
+use crate::contract::{spread_monthly_fee, Contract, Seasonal, TimeOfUse};
+use crate::priceclient::start_of_hour;
+use crate::record::{Record, RecordWithPrice};
+use crate::vat::{finnish_vat_rate, VatPolicy};
+
+/// Electricity tax class
+#[derive(clap::ValueEnum, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TaxClass {
+    /// Households and services
+    ClassI,
+    /// Industry, data centers and professional greenhouses
+    ClassII,
+}
+
+/// Electricity tax including the security of supply fee.
+///
+/// The rates are statutory VAT 0 % amounts, so VAT is added to them for a gross comparison.
+#[derive(Debug, Copy, Clone)]
+pub struct ElectricityTax {
+    pub class: TaxClass,
+    pub vat: VatPolicy,
+}
+
+impl ElectricityTax {
+    pub fn new(class: TaxClass, vat: VatPolicy) -> Self {
+        ElectricityTax { class, vat }
+    }
+
+    /// Tax rate in c/kWh without VAT at `time`
+    pub fn rate(&self, time: &DateTime<Utc>) -> Decimal {
+        let date = time.with_timezone(&Helsinki).date_naive();
+        match self.class {
+            TaxClass::ClassI => dec!(2.253),
+            TaxClass::ClassII if date >= NaiveDate::from_ymd(2022, 1, 1) => dec!(0.063),
+            TaxClass::ClassII => dec!(0.703),
+        }
+    }
+}
+
+impl<R> Contract<R> for ElectricityTax
+where
+    R: Record,
+{
+    fn name(&self) -> String {
+        match self.class {
+            TaxClass::ClassI => "Sähkövero I".to_string(),
+            TaxClass::ClassII => "Sähkövero II".to_string(),
+        }
+    }
+
+    fn cost(&self, record: &RecordWithPrice<R>) -> Decimal {
+        let time = record.record.start_time();
+        let rate = match self.vat {
+            VatPolicy::Net => self.rate(&time),
+            VatPolicy::Gross => self.rate(&time) * (Decimal::ONE + finnish_vat_rate(&time)),
+        };
+        rate * record.record.energy()
+    }
+}
+
+/// Energy based part of a distribution tariff, prices in c/kWh
+#[derive(Debug, Copy, Clone)]
+pub enum TransferPricing {
+    Flat(Decimal),
+    /// Night time is 22-07 local time
+    DayNight { day: Decimal, night: Decimal },
+    /// Winter weekday is November-March, Monday-Saturday 07-22 local time
+    Seasonal { winter_weekday: Decimal, other: Decimal },
+}
+
+/// Distribution (DSO transfer) tariff with a monthly basic fee in euros
+#[derive(Debug, Copy, Clone)]
+pub struct NetworkTariff {
+    pub pricing: TransferPricing,
+    pub monthly_fee: Decimal,
+    pub timezone: Tz,
+}
+
+impl NetworkTariff {
+    pub fn new(pricing: TransferPricing, monthly_fee: Decimal, timezone: Tz) -> Self {
+        NetworkTariff {
+            pricing,
+            monthly_fee,
+            timezone,
+        }
+    }
+
+    /// Transfer price in c/kWh for an interval starting at `time`
+    pub fn price(&self, time: &DateTime<Utc>) -> Decimal {
+        match self.pricing {
+            TransferPricing::Flat(price) => price,
+            TransferPricing::DayNight { day, night } => {
+                if TimeOfUse::new(day, night, self.timezone).is_day(time) {
+                    day
+                } else {
+                    night
+                }
+            }
+            TransferPricing::Seasonal {
+                winter_weekday,
+                other,
+            } => {
+                if Seasonal::new(winter_weekday, other, self.timezone).is_winter_weekday(time) {
+                    winter_weekday
+                } else {
+                    other
+                }
+            }
+        }
+    }
+}
+
+impl<R> Contract<R> for NetworkTariff
+where
+    R: Record,
+{
+    fn name(&self) -> String {
+        "Siirto".to_string()
+    }
+
+    /// Cost without the basic fee, which can only be divided when all records of the month are
+    /// known
+    fn cost(&self, record: &RecordWithPrice<R>) -> Decimal {
+        self.price(&record.record.start_time()) * record.record.energy()
+    }
+
+    fn costs(&self, records: &[RecordWithPrice<R>]) -> Vec<Decimal> {
+        records
+            .iter()
+            .zip(spread_monthly_fee(records, self.timezone, |_, _| self.monthly_fee))
+            .map(|(record, fee)| self.cost(record) + fee)
+            .collect()
+    }
+}
+
+/// Costs paid on top of every energy contract
+#[derive(Debug, Default, Copy, Clone)]
+pub struct GridCosts {
+    pub network: Option<NetworkTariff>,
+    pub power: Option<PowerTariff>,
+    pub tax: Option<ElectricityTax>,
+}
+
+/// Grid costs of a single record in cents
+#[derive(Debug, Default, Copy, Clone)]
+pub struct GridCost {
+    /// Distribution cost including the share of the basic fee and power fee
+    pub transfer: Decimal,
+    pub tax: Decimal,
+}
+
+impl GridCost {
+    pub fn total(&self) -> Decimal {
+        self.transfer + self.tax
+    }
+}
+
+impl GridCosts {
+    /// Costs of `records` in the same order
+    pub fn costs<R>(&self, records: &[RecordWithPrice<R>]) -> Vec<GridCost>
+    where
+        R: Record,
+    {
+        let zero = || vec![Decimal::ZERO; records.len()];
+        let transfer = self.network.map(|n| n.costs(records)).unwrap_or_else(zero);
+        let power = self.power.map(|p| p.costs(records)).unwrap_or_else(zero);
+        let tax = self.tax.map(|t| t.costs(records)).unwrap_or_else(zero);
+        transfer
+            .into_iter()
+            .zip(power)
+            .zip(tax)
+            .map(|((transfer, power), tax)| GridCost {
+                transfer: transfer + power,
+                tax,
+            })
+            .collect()
+    }
+}
+
+/// Power based distribution fee, charged on the highest hourly demand of each calendar month
+/// in local time
+#[derive(Debug, Copy, Clone)]
+pub struct PowerTariff {
+    /// €/kW per month
+    pub price: Decimal,
+    /// Number of highest hours averaged into the monthly peak demand
+    pub peak_hours: usize,
+    pub timezone: Tz,
+}
+
+/// Power fee of one calendar month
+#[derive(Debug, Clone)]
+pub struct MonthlyPowerFee {
+    pub year: i32,
+    pub month: u32,
+    /// Start and energy in kWh of the hours that set the peak, highest first
+    pub peak_hours: Vec<(DateTime<Utc>, Decimal)>,
+    /// Peak demand in kW
+    pub demand: Decimal,
+    /// Fee in euros
+    pub fee: Decimal,
+}
+
+impl PowerTariff {
+    pub fn new(price: Decimal, peak_hours: usize, timezone: Tz) -> Self {
+        PowerTariff {
+            price,
+            peak_hours: peak_hours.max(1),
+            timezone,
+        }
+    }
+
+    pub fn monthly_fees<R>(&self, bins: &Bins<R>) -> Vec<MonthlyPowerFee>
+    where
+        R: Record,
+    {
+        // energy of an hour in kWh is the average demand of that hour in kW
+        let mut hourly: BTreeMap<DateTime<Utc>, Decimal> = BTreeMap::new();
+        for record in bins.iter().flat_map(|b| b.records()) {
+            *hourly
+                .entry(start_of_hour(&record.start_time()))
+                .or_default() += record.energy();
+        }
+
+        let mut months: BTreeMap<(i32, u32), Vec<_>> = BTreeMap::new();
+        for (hour, energy) in hourly {
+            let local = hour.with_timezone(&self.timezone);
+            months
+                .entry((local.year(), local.month()))
+                .or_default()
+                .push((hour, energy));
+        }
+
+        months
+            .into_iter()
+            .map(|((year, month), mut hours)| {
+                hours.sort_by_key(|(_, energy)| Reverse(*energy));
+                hours.truncate(self.peak_hours);
+                let demand =
+                    hours.iter().map(|(_, e)| *e).sum::<Decimal>() / Decimal::from(hours.len());
+                MonthlyPowerFee {
+                    year,
+                    month,
+                    peak_hours: hours,
+                    demand,
+                    fee: demand * self.price,
+                }
+            })
+            .collect()
+    }
+}
+
+impl<R> Contract<R> for PowerTariff
+where
+    R: Record,
+{
+    fn name(&self) -> String {
+        "Tehomaksu".to_string()
+    }
+
+    /// The fee can only be known when all records of the month are known
+    fn cost(&self, _record: &RecordWithPrice<R>) -> Decimal {
+        Decimal::ZERO
+    }
+
+    fn costs(&self, records: &[RecordWithPrice<R>]) -> Vec<Decimal> {
+        let bins = Bins::new(records.iter().map(|r| r.record), self.timezone);
+        let fees = self
+            .monthly_fees(&bins)
+            .into_iter()
+            .map(|f| ((f.year, f.month), f.fee))
+            .collect::<HashMap<_, _>>();
+        spread_monthly_fee(records, self.timezone, |year, month| {
+            fees.get(&(year, month)).copied().unwrap_or_default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone};
+
+    use super::*;
+    use crate::record::oomi::OomiRecord;
+    use crate::record::TimeResolution;
+
+    fn local(y: i32, m: u32, d: u32, h: u32) -> DateTime<Utc> {
+        Helsinki.ymd(y, m, d).and_hms(h, 0, 0).with_timezone(&Utc)
+    }
+
+    /// Reading of `resolution` starting at `start`
+    fn reading(start: DateTime<Utc>, resolution: TimeResolution, energy: Decimal) -> OomiRecord {
+        OomiRecord {
+            date_time: start + resolution.duration(),
+            energy,
+            resolution,
+            ..Default::default()
+        }
+    }
+
+    fn hourly(start: DateTime<Utc>, energy: i64) -> OomiRecord {
+        reading(start, TimeResolution::PT1H, Decimal::from(energy))
+    }
+
+    fn with_prices(records: &[OomiRecord]) -> Vec<RecordWithPrice<'_, OomiRecord>> {
+        records.iter().map(|r| RecordWithPrice::new(r, Decimal::ZERO)).collect()
+    }
+
+    #[test]
+    fn electricity_tax_rates() {
+        let class_i = ElectricityTax::new(TaxClass::ClassI, VatPolicy::Net);
+        let class_ii = ElectricityTax::new(TaxClass::ClassII, VatPolicy::Net);
+
+        assert_eq!(class_i.rate(&local(2021, 12, 31, 23)), dec!(2.253));
+        assert_eq!(class_i.rate(&local(2022, 1, 1, 0)), dec!(2.253));
+        assert_eq!(class_ii.rate(&local(2021, 12, 31, 23)), dec!(0.703));
+        assert_eq!(class_ii.rate(&local(2022, 1, 1, 0)), dec!(0.063));
+    }
+
+    #[test]
+    fn electricity_tax_includes_vat_in_gross_comparison() {
+        let records = [hourly(local(2024, 1, 10, 12), 2)];
+        let net = ElectricityTax::new(TaxClass::ClassI, VatPolicy::Net);
+        let gross = ElectricityTax::new(TaxClass::ClassI, VatPolicy::Gross);
+
+        assert_eq!(net.costs(&with_prices(&records)), vec![dec!(4.506)]);
+        assert_eq!(gross.costs(&with_prices(&records)), vec![dec!(5.58744)]);
+    }
+
+    #[test]
+    fn network_tariff_spreads_the_monthly_fee() {
+        let tariff = NetworkTariff::new(TransferPricing::Flat(Decimal::from(5)), Decimal::from(10), Helsinki);
+        let records = [
+            hourly(local(2023, 1, 10, 12), 1),
+            hourly(local(2023, 1, 31, 23), 2),
+            hourly(local(2023, 2, 1, 0), 1),
+        ];
+
+        // 10 € is 1000 c, split between the two readings of January
+        assert_eq!(
+            tariff.costs(&with_prices(&records)),
+            vec![Decimal::from(505), Decimal::from(510), Decimal::from(1005)]
+        );
+    }
+
+    #[test]
+    fn network_tariff_day_and_night_prices() {
+        let pricing = TransferPricing::DayNight {
+            day: Decimal::from(4),
+            night: Decimal::from(2),
+        };
+        let tariff = NetworkTariff::new(pricing, Decimal::ZERO, Helsinki);
+
+        assert_eq!(tariff.price(&local(2023, 1, 10, 6)), Decimal::from(2));
+        assert_eq!(tariff.price(&local(2023, 1, 10, 7)), Decimal::from(4));
+        assert_eq!(tariff.price(&local(2023, 1, 10, 22)), Decimal::from(2));
+    }
+
+    #[test]
+    fn grid_costs_add_up_the_components() {
+        let costs = GridCosts {
+            network: Some(NetworkTariff::new(TransferPricing::Flat(Decimal::from(5)), Decimal::ZERO, Helsinki)),
+            power: None,
+            tax: Some(ElectricityTax::new(TaxClass::ClassI, VatPolicy::Net)),
+        };
+        let records = [hourly(local(2023, 1, 10, 12), 1)];
+        let cost = costs.costs(&with_prices(&records))[0];
+
+        assert_eq!(cost.transfer, Decimal::from(5));
+        assert_eq!(cost.tax, dec!(2.253));
+        assert_eq!(cost.total(), dec!(7.253));
+    }
+
+    #[test]
+    fn power_fee_sums_quarters_into_hours() {
+        let tariff = PowerTariff::new(Decimal::from(2), 1, Helsinki);
+        let start = local(2023, 1, 10, 12);
+        let mut records = (0..4)
+            .map(|q| reading(start + Duration::minutes(15 * q), TimeResolution::PT15M, dec!(1.5)))
+            .collect::<Vec<_>>();
+        records.push(hourly(local(2023, 1, 10, 14), 5));
+        let fees = tariff.monthly_fees(&Bins::new(records, Helsinki));
+
+        assert_eq!(fees.len(), 1);
+        assert_eq!(fees[0].peak_hours, vec![(start, Decimal::from(6))]);
+        assert_eq!(fees[0].demand, Decimal::from(6));
+        assert_eq!(fees[0].fee, Decimal::from(12));
+    }
+
+    #[test]
+    fn power_fee_averages_the_highest_hours_of_each_local_month() {
+        let tariff = PowerTariff::new(Decimal::from(2), 2, Helsinki);
+        let records = vec![
+            hourly(local(2023, 3, 1, 12), 1),
+            hourly(local(2023, 3, 20, 12), 3),
+            hourly(local(2023, 3, 31, 23), 5),
+            // 21:00 UTC, after the change to summer time on the 26th
+            hourly(local(2023, 4, 1, 0), 9),
+        ];
+        let fees = tariff.monthly_fees(&Bins::new(records.clone(), Helsinki));
+
+        assert_eq!(fees.iter().map(|f| (f.year, f.month)).collect::<Vec<_>>(), vec![(2023, 3), (2023, 4)]);
+        assert_eq!(fees[0].demand, Decimal::from(4));
+        assert_eq!(fees[0].fee, Decimal::from(8));
+        assert_eq!(fees[1].demand, Decimal::from(9));
+
+        // the fee of each month is spread over its records, in cents
+        let costs = tariff.costs(&with_prices(&records));
+        assert_eq!(costs.iter().take(3).sum::<Decimal>(), Decimal::from(800));
+        assert_eq!(costs[3], Decimal::from(1800));
+    }
+
+    #[test]
+    fn power_fee_keeps_the_repeated_hour_of_the_dst_change() {
+        let tariff = PowerTariff::new(Decimal::ONE, 2, Helsinki);
+        // 03:00 local time twice on 2023-10-29
+        let first = Utc.ymd(2023, 10, 29).and_hms(0, 0, 0);
+        let records = vec![hourly(first, 2), hourly(first + Duration::hours(1), 4)];
+        let fees = tariff.monthly_fees(&Bins::new(records, Helsinki));
+
+        assert_eq!(fees.len(), 1);
+        assert_eq!(fees[0].peak_hours.len(), 2);
+        assert_eq!(fees[0].demand, Decimal::from(3));
+    }
+}
@@ -0,0 +1,142 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use chrono_tz::Europe::Helsinki;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+use crate::error::Error;
+use crate::priceclient::{PriceSource, SpotPrice};
+
+/// Whether compared prices include VAT.
+///
+/// Spot prices are published without VAT, while retail prices are quoted including it. Contract
+/// prices and margins are taken as given, so they should be entered with VAT for a gross
+/// comparison and without VAT for a net comparison.
+#[derive(clap::ValueEnum, Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum VatPolicy {
+    /// Spot prices without VAT
+    #[default]
+    Net,
+    /// Spot prices with the Finnish VAT in force at the time
+    Gross,
+}
+
+/// Finnish VAT rate on electricity at `time`
+pub fn finnish_vat_rate(time: &DateTime<Utc>) -> Decimal {
+    let date = time.with_timezone(&Helsinki).date_naive();
+    let since = |y, m, d| date >= NaiveDate::from_ymd(y, m, d);
+    if since(2024, 9, 1) {
+        dec!(0.255)
+    } else if since(2023, 5, 1) {
+        dec!(0.24)
+    } else if since(2022, 12, 1) {
+        // temporary reduction for December 2022 - April 2023
+        dec!(0.10)
+    } else if since(2013, 1, 1) {
+        dec!(0.24)
+    } else if since(2010, 7, 1) {
+        dec!(0.23)
+    } else {
+        dec!(0.22)
+    }
+}
+
+/// Applies a [`VatPolicy`] to the VAT 0 % prices of another source
+pub struct WithVat<P> {
+    source: P,
+    policy: VatPolicy,
+}
+
+impl<P> WithVat<P>
+where
+    P: PriceSource,
+{
+    pub fn new(source: P, policy: VatPolicy) -> Self {
+        WithVat { source, policy }
+    }
+}
+
+impl<P> PriceSource for WithVat<P>
+where
+    P: PriceSource,
+{
+    fn get_prices(
+        &self,
+        start: &DateTime<Utc>,
+        end: &DateTime<Utc>,
+    ) -> Result<Vec<SpotPrice>, Error> {
+        let prices = self.source.get_prices(start, end)?;
+        let prices = match self.policy {
+            VatPolicy::Net => prices,
+            VatPolicy::Gross => prices
+                .into_iter()
+                .map(|p| SpotPrice {
+                    price: p.price * (Decimal::ONE + finnish_vat_rate(&p.time)),
+                    ..p
+                })
+                .collect(),
+        };
+        Ok(prices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::record::TimeResolution;
+
+    fn local(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Utc> {
+        Helsinki.ymd(y, m, d).and_hms(h, min, 0).with_timezone(&Utc)
+    }
+
+    #[test]
+    fn rates_change_at_local_midnight() {
+        let table = [
+            (local(2010, 6, 30, 23, 59), dec!(0.22)),
+            (local(2010, 7, 1, 0, 0), dec!(0.23)),
+            (local(2012, 12, 31, 23, 59), dec!(0.23)),
+            (local(2013, 1, 1, 0, 0), dec!(0.24)),
+            (local(2022, 11, 30, 23, 59), dec!(0.24)),
+            (local(2022, 12, 1, 0, 0), dec!(0.10)),
+            (local(2023, 4, 30, 23, 59), dec!(0.10)),
+            (local(2023, 5, 1, 0, 0), dec!(0.24)),
+            (local(2024, 8, 31, 23, 59), dec!(0.24)),
+            (local(2024, 9, 1, 0, 0), dec!(0.255)),
+        ];
+        for (time, rate) in table {
+            assert_eq!(finnish_vat_rate(&time), rate, "{}", time);
+        }
+    }
+
+    struct Prices(Vec<SpotPrice>);
+
+    impl PriceSource for Prices {
+        fn get_prices(&self, _: &DateTime<Utc>, _: &DateTime<Utc>) -> Result<Vec<SpotPrice>, Error> {
+            Ok(self.0.clone())
+        }
+    }
+
+    fn gross_and_net(time: DateTime<Utc>) -> (Decimal, Decimal) {
+        let price = |policy| {
+            let source = Prices(vec![SpotPrice {
+                time,
+                price: Decimal::from(10),
+                resolution: TimeResolution::PT1H,
+            }]);
+            WithVat::new(source, policy).get_prices(&time, &time).unwrap()[0].price
+        };
+        (price(VatPolicy::Gross), price(VatPolicy::Net))
+    }
+
+    #[test]
+    fn net_prices_are_kept_and_gross_prices_include_vat() {
+        assert_eq!(gross_and_net(local(2023, 1, 1, 12, 0)), (dec!(11.0), Decimal::from(10)));
+        assert_eq!(gross_and_net(local(2025, 1, 1, 12, 0)), (dec!(12.55), Decimal::from(10)));
+    }
+
+    #[test]
+    fn net_is_the_default() {
+        assert_eq!(VatPolicy::default(), VatPolicy::Net);
+    }
+}